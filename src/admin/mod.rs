@@ -0,0 +1,185 @@
+use crate::{batcher::BatcherHandle, network::accept, MempoolQuery, Transaction};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+
+/// Renders the config in effect
+type ConfigFn = Arc<dyn Fn() -> serde_json::Result<Value> + Send + Sync>;
+
+/// The requests understood by the admin endpoint, one JSON object per line,
+/// e.g. `{"cmd": "drop_sync", "digest": "..."}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum AdminRequest {
+    /// The state of the sealer and of the synchronizer
+    Status,
+    /// The digests of the batches the synchronizer is waiting for
+    PendingSync,
+    /// What the synchronizer observed about every peer
+    Peers,
+    /// The configuration in effect
+    Config,
+    /// Seals the waiting transactions right away
+    Seal,
+    /// Deletes the batches older than the gc round right away
+    Gc,
+    /// Stops waiting for a batch, as printed by `pending_sync`
+    DropSync { digest: String },
+}
+
+/// Answers the requests of the admin endpoint, for a running mempool
+pub struct Admin<Storage, Tx> {
+    query: MempoolQuery<Storage, Tx>,
+    /// The batcher, if it is driven by this node
+    batcher: Option<BatcherHandle>,
+    /// The configuration in effect
+    config: ConfigFn,
+}
+
+impl<Storage, Tx> Clone for Admin<Storage, Tx>
+where
+    Storage: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            query: self.query.clone(),
+            batcher: self.batcher.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<Storage, Tx> Admin<Storage, Tx>
+where
+    Storage: libstorage::Store,
+    Tx: Transaction,
+{
+    pub fn new<Config>(
+        query: MempoolQuery<Storage, Tx>,
+        batcher: Option<BatcherHandle>,
+        rx_config: watch::Receiver<Config>,
+    ) -> Self
+    where
+        Config: Serialize + Send + Sync + 'static,
+    {
+        Self {
+            query,
+            batcher,
+            config: Arc::new(move || serde_json::to_value(&*rx_config.borrow())),
+        }
+    }
+
+    /// Answers a single request
+    pub async fn handle(
+        &self,
+        request: AdminRequest,
+    ) -> Result<Value> {
+        let result = match request {
+            AdminRequest::Status => {
+                let sealer_pending = match &self.batcher {
+                    Some(batcher) => Some(batcher.pending().await?),
+                    None => None,
+                };
+                json!({
+                    "sealer_pending": sealer_pending,
+                    "sync": self.query.status().await?,
+                })
+            }
+            AdminRequest::PendingSync => {
+                let pending: Vec<String> = self
+                    .query
+                    .pending()
+                    .await?
+                    .iter()
+                    .map(|hash| hash.to_string())
+                    .collect();
+                json!(pending)
+            }
+            AdminRequest::Peers => json!(self.query.peers().await?),
+            AdminRequest::Config => (self.config)()?,
+            AdminRequest::Seal => match &self.batcher {
+                Some(batcher) => json!({ "sealed": batcher.seal().await? }),
+                None => return Err(anyhow!("The batcher is not driven by this node")),
+            },
+            AdminRequest::Gc => json!({ "collected": self.query.gc().await? }),
+            AdminRequest::DropSync { digest } => {
+                let hash = self
+                    .query
+                    .pending()
+                    .await?
+                    .into_iter()
+                    .find(|hash| hash.to_string() == digest)
+                    .ok_or_else(|| anyhow!("Not waiting for batch {}", digest))?;
+                json!({ "dropped": self.query.drop_sync(&hash).await? })
+            }
+        };
+        Ok(result)
+    }
+
+    /// Answers one line of the protocol, with `{"ok": true, "result": ...}` or
+    /// `{"ok": false, "error": "..."}`
+    async fn handle_line(
+        &self,
+        line: &str,
+    ) -> Value {
+        let result = match serde_json::from_str::<AdminRequest>(line) {
+            Ok(request) => self.handle(request).await,
+            Err(e) => Err(anyhow!("Invalid request: {}", e)),
+        };
+        match result {
+            Ok(result) => json!({ "ok": true, "result": result }),
+            Err(e) => json!({ "ok": false, "error": e.to_string() }),
+        }
+    }
+
+    async fn serve_connection(
+        self,
+        socket: TcpStream,
+    ) -> Result<()> {
+        let (reader, mut writer) = socket.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut response = self.handle_line(&line).await.to_string();
+            response.push('\n');
+            writer.write_all(response.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Serves the admin endpoint on `addr`, which must be a loopback address
+pub async fn serve_admin<Storage, Tx>(
+    admin: Admin<Storage, Tx>,
+    addr: SocketAddr,
+) -> Result<()>
+where
+    Storage: libstorage::Store,
+    Tx: Transaction,
+{
+    if !addr.ip().is_loopback() {
+        return Err(anyhow!("The admin endpoint must listen on a loopback address, not {}", addr));
+    }
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Serving admin requests on {}", addr);
+    tokio::spawn(async move {
+        loop {
+            let (socket, peer) = accept(&listener, "Admin").await;
+            let admin = admin.clone();
+            tokio::spawn(async move {
+                if let Err(e) = admin.serve_connection(socket).await {
+                    log::debug!("Admin connection with {} failed: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use super::{BatchStore, Namespace};
+use crate::BatchHash;
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// The entries of the index are numbered from `first` (included) to `next`
+/// (excluded). Some of them may have been removed since.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Bounds {
+    first: u64,
+    next: u64,
+}
+
+/// The persisted index of the stored batches, that `BatchStore::scan` walks
+///
+/// Every digest has an entry of its own, numbered in the order it was indexed,
+/// so that indexing a batch costs the same whatever the size of the store.
+/// Batches are mostly collected in the order they were stored, so the entries
+/// removed at the front are skipped for good.
+///
+/// The index has a single writer, which owns it: the synchronizer.
+pub struct BatchIndex<Storage, Tx> {
+    store: BatchStore<Storage, Tx>,
+    bounds: Bounds,
+}
+
+impl<Storage, Tx> BatchIndex<Storage, Tx>
+where
+    Storage: libstorage::Store,
+    Tx: Serialize + DeserializeOwned,
+{
+    /// Picks up the index where the previous run left it
+    pub async fn load(mut store: BatchStore<Storage, Tx>) -> Result<Self> {
+        let bounds = store.read_bounds().await?;
+        Ok(Self { store, bounds })
+    }
+
+    /// Adds a batch to the index, unless it already is
+    pub async fn add(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) -> Result<()> {
+        if self.store.read_position(digest).await?.is_some() {
+            return Ok(());
+        }
+        // Claim the entry first, so that a crash leaves a hole rather than
+        // an entry that the next one overwrites
+        let position = self.bounds.next;
+        self.bounds.next += 1;
+        self.write_bounds().await;
+        let store = &mut self.store.store;
+        store.write(Namespace::entry(position), digest.to_vec()).await;
+        let key = Namespace::IndexPosition.key(digest);
+        store.write(key, position.to_be_bytes().to_vec()).await;
+        Ok(())
+    }
+
+    /// Removes a batch from the index
+    pub async fn remove(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) -> Result<()> {
+        let position = match self.store.read_position(digest).await? {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+        let store = &mut self.store.store;
+        store.delete(Namespace::entry(position)).await;
+        store.delete(Namespace::IndexPosition.key(digest)).await;
+
+        if position == self.bounds.first {
+            let mut first = position + 1;
+            while first < self.bounds.next && self.store.read_entry(first).await?.is_none() {
+                first += 1;
+            }
+            self.bounds.first = first;
+            self.write_bounds().await;
+        }
+        Ok(())
+    }
+
+    async fn write_bounds(&mut self) {
+        let data = bincode::serialize(&self.bounds).expect("Failed to serialize index bounds");
+        self.store.store.write(Namespace::bounds(), data).await;
+    }
+}
+
+impl<Storage, Tx> BatchStore<Storage, Tx>
+where
+    Storage: libstorage::Store,
+    Tx: Serialize + DeserializeOwned,
+{
+    /// The digests of the indexed batches. `libstorage::Store` cannot iterate
+    /// over its keys, so this walks the entries of the index instead.
+    pub async fn scan(&mut self) -> Result<Vec<BatchHash<Tx>>> {
+        let bounds = self.read_bounds().await?;
+        let mut digests = Vec::new();
+        for position in bounds.first..bounds.next {
+            if let Some(digest) = self.read_entry(position).await? {
+                digests.push(digest);
+            }
+        }
+        Ok(digests)
+    }
+
+    async fn read_bounds(&mut self) -> Result<Bounds> {
+        match self.store.read(Namespace::bounds()).await? {
+            Some(data) => Ok(bincode::deserialize(&data)?),
+            None => Ok(Bounds::default()),
+        }
+    }
+
+    async fn read_entry(
+        &mut self,
+        position: u64,
+    ) -> Result<Option<BatchHash<Tx>>> {
+        match self.store.read(Namespace::entry(position)).await? {
+            Some(data) => Ok(Some(data[..].try_into().map_err(|_| {
+                anyhow!("Invalid batch digest in index entry {}", position)
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn read_position(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) -> Result<Option<u64>> {
+        match self.store.read(Namespace::IndexPosition.key(digest)).await? {
+            Some(data) => {
+                let bytes = data[..].try_into().map_err(|_| {
+                    anyhow!("Invalid index position for batch {}", digest)
+                })?;
+                Ok(Some(u64::from_be_bytes(bytes)))
+            }
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{tx_hash, Batch, BatchHash, TxHash};
+use anyhow::{anyhow, Result};
+use libcrypto::hash::Hash;
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+
+pub use index::*;
+
+mod index;
+
+/// The namespaces of the keys in the store. Every key is the digest of a batch
+/// (or transaction) prefixed by the tag of its namespace, but for the entries
+/// of the index, which are numbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Namespace {
+    /// Serialized batches, by batch digest
+    Batch,
+    /// Batch metadata, by batch digest
+    Meta,
+    /// The digest of the batch containing a transaction, by transaction digest
+    TxIndex,
+    /// The digests of the transactions of a batch, by batch digest
+    Txs,
+    /// Corrupted batches, by batch digest
+    Quarantine,
+    /// The digests of the stored batches, by the order they were indexed
+    Index,
+    /// The number of the index entry of a batch, by batch digest
+    IndexPosition,
+}
+
+impl Namespace {
+    fn tag(self) -> u8 {
+        match self {
+            Namespace::Batch => b'b',
+            Namespace::Meta => b'm',
+            Namespace::TxIndex => b't',
+            Namespace::Txs => b'x',
+            Namespace::Quarantine => b'q',
+            Namespace::Index => b'i',
+            Namespace::IndexPosition => b'p',
+        }
+    }
+
+    fn key<T>(
+        self,
+        digest: &Hash<T>,
+    ) -> Vec<u8> {
+        [&[self.tag()][..], &digest.to_vec()].concat()
+    }
+
+    /// The key of an entry of the index
+    fn entry(position: u64) -> Vec<u8> {
+        [&[Namespace::Index.tag()][..], &position.to_be_bytes()].concat()
+    }
+
+    /// The key of the bounds of the index
+    fn bounds() -> Vec<u8> {
+        vec![Namespace::Index.tag()]
+    }
+
+    /// The key used before namespaces were introduced, if any. Such keys are
+    /// still read, but never written.
+    fn legacy_key<T>(
+        self,
+        digest: &Hash<T>,
+    ) -> Option<Vec<u8>> {
+        match self {
+            Namespace::Batch => Some(digest.to_vec()),
+            Namespace::TxIndex => Some([&b"tx"[..], &digest.to_vec()].concat()),
+            Namespace::Quarantine => Some([&b"quarantine"[..], &digest.to_vec()].concat()),
+            Namespace::Meta | Namespace::Txs | Namespace::Index | Namespace::IndexPosition => {
+                None
+            }
+        }
+    }
+}
+
+/// A typed view over a `libstorage::Store`, to store batches (and everything
+/// we know about them) by their digests.
+///
+/// This is the only place that knows how keys and values are encoded.
+pub struct BatchStore<Storage, Tx> {
+    store: Storage,
+    _x: PhantomData<Tx>,
+}
+
+impl<Storage, Tx> Clone for BatchStore<Storage, Tx>
+where
+    Storage: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            _x: PhantomData,
+        }
+    }
+}
+
+impl<Storage, Tx> BatchStore<Storage, Tx>
+where
+    Storage: libstorage::Store,
+    Tx: Serialize + DeserializeOwned,
+{
+    pub fn new(store: Storage) -> Self {
+        Self {
+            store,
+            _x: PhantomData,
+        }
+    }
+
+    /// Serializes a batch
+    pub fn encode(batch: &Batch<Tx>) -> Vec<u8> {
+        bincode::serialize(batch).expect("Failed to serialize batch")
+    }
+
+    /// Deserializes a batch
+    pub fn decode(data: &[u8]) -> Result<Batch<Tx>> {
+        Ok(bincode::deserialize(data)?)
+    }
+
+    /// Computes the digest of a serialized batch
+    pub fn digest(data: &[u8]) -> BatchHash<Tx> {
+        Hash::do_hash(data)
+    }
+
+    /// Stores a batch and indexes its transactions. Returns its digest.
+    ///
+    /// A transaction already indexed under another batch is deliberately
+    /// re-indexed under this one: lookups return the latest batch that
+    /// carried it.
+    pub async fn put(
+        &mut self,
+        batch: &Batch<Tx>,
+    ) -> BatchHash<Tx> {
+        let data = Self::encode(batch);
+        let digest = Self::digest(&data);
+        let txs: Vec<TxHash<Tx>> = batch.payload.iter().map(tx_hash).collect();
+        self.put_raw(&digest, data).await;
+        for tx_digest in &txs {
+            self.store
+                .write(Namespace::TxIndex.key(tx_digest), digest.to_vec())
+                .await;
+        }
+        // Written last, as it also tells that the batch is stored
+        let txs = bincode::serialize(&txs).expect("Failed to serialize transaction digests");
+        self.store.write(Namespace::Txs.key(&digest), txs).await;
+        digest
+    }
+
+    /// Stores a serialized batch, as is
+    pub async fn put_raw(
+        &mut self,
+        digest: &BatchHash<Tx>,
+        data: Vec<u8>,
+    ) {
+        self.store.write(Namespace::Batch.key(digest), data).await;
+    }
+
+    /// Fetches a batch
+    pub async fn get(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) -> Result<Option<Batch<Tx>>> {
+        match self.get_raw(digest).await? {
+            Some(data) => Ok(Some(Self::decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fetches a serialized batch, as stored
+    pub async fn get_raw(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.read(Namespace::Batch, digest).await
+    }
+
+    /// Checks whether a batch is stored, without deserializing it. The store
+    /// cannot look a key up without reading its value, so this reads the
+    /// (short) digests of its transactions rather than the batch itself, but
+    /// for batches stored as is or before those were kept.
+    pub async fn exists(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) -> Result<bool> {
+        if self.store.read(Namespace::Txs.key(digest)).await?.is_some() {
+            return Ok(true);
+        }
+        Ok(self.get_raw(digest).await?.is_some())
+    }
+
+    /// Waits for a batch to be stored, and returns it serialized
+    pub async fn notify_read(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) -> Result<Vec<u8>> {
+        // The batch may be stored under its legacy key
+        if let Some(data) = self.get_raw(digest).await? {
+            return Ok(data);
+        }
+        Ok(self.store.notify_read(Namespace::Batch.key(digest)).await?)
+    }
+
+    /// Deletes a batch, along with its metadata and the index of its
+    /// transactions. Transactions since re-indexed under another batch keep
+    /// their entry.
+    pub async fn delete(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) {
+        self.unindex(digest).await;
+        self.store.delete(Namespace::Meta.key(digest)).await;
+        self.store.delete(Namespace::Batch.key(digest)).await;
+        if let Some(legacy) = Namespace::Batch.legacy_key(digest) {
+            self.store.delete(legacy).await;
+        }
+    }
+
+    /// Moves a (corrupted) batch out of the way, so that it is neither served
+    /// nor mistaken for the real one. Its transactions are unindexed until the
+    /// real one is stored again.
+    pub async fn quarantine(
+        &mut self,
+        digest: &BatchHash<Tx>,
+        data: Vec<u8>,
+    ) {
+        self.unindex(digest).await;
+        self.store.write(Namespace::Quarantine.key(digest), data).await;
+        self.store.delete(Namespace::Batch.key(digest)).await;
+        if let Some(legacy) = Namespace::Batch.legacy_key(digest) {
+            self.store.delete(legacy).await;
+        }
+    }
+
+    /// Removes the index entries of the transactions of a batch that still
+    /// point to it. Batches stored before the digests of their transactions
+    /// were kept are decoded instead, and leak their entries if they cannot be.
+    async fn unindex(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) {
+        let txs = match self.transactions(digest).await {
+            Ok(txs) => txs,
+            Err(e) => {
+                log::warn!("Failed to unindex the transactions of batch {}: {}", digest, e);
+                Vec::new()
+            }
+        };
+        for tx_digest in txs {
+            if let Ok(Some(indexed)) = self.batch_of(&tx_digest).await {
+                if &indexed == digest {
+                    self.store.delete(Namespace::TxIndex.key(&tx_digest)).await;
+                    if let Some(legacy) = Namespace::TxIndex.legacy_key(&tx_digest) {
+                        self.store.delete(legacy).await;
+                    }
+                }
+            }
+        }
+        self.store.delete(Namespace::Txs.key(digest)).await;
+    }
+
+    /// Returns the digests of the transactions of a batch
+    async fn transactions(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) -> Result<Vec<TxHash<Tx>>> {
+        if let Some(data) = self.store.read(Namespace::Txs.key(digest)).await? {
+            return Ok(bincode::deserialize(&data)?);
+        }
+        Ok(match self.get(digest).await? {
+            Some(batch) => batch.payload.iter().map(tx_hash).collect(),
+            None => Vec::new(),
+        })
+    }
+
+    /// Returns the digest of the batch containing a transaction
+    pub async fn batch_of(
+        &mut self,
+        digest: &TxHash<Tx>,
+    ) -> Result<Option<BatchHash<Tx>>> {
+        match self.read(Namespace::TxIndex, digest).await? {
+            Some(data) => Ok(Some(data[..].try_into().map_err(|_| {
+                anyhow!("Invalid batch digest for transaction {}", digest)
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a key of a namespace, falling back to its legacy key. Nothing
+    /// is written, so that readers never race with deletions.
+    async fn read<T>(
+        &mut self,
+        namespace: Namespace,
+        digest: &Hash<T>,
+    ) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.store.read(namespace.key(digest)).await? {
+            return Ok(Some(data));
+        }
+        match namespace.legacy_key(digest) {
+            Some(legacy) => Ok(self.store.read(legacy).await?),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores the metadata of a batch
+    pub async fn put_meta<M>(
+        &mut self,
+        digest: &BatchHash<Tx>,
+        meta: &M,
+    ) where
+        M: Serialize,
+    {
+        let data = bincode::serialize(meta).expect("Failed to serialize batch metadata");
+        self.store.write(Namespace::Meta.key(digest), data).await;
+    }
+
+    /// Fetches the metadata of a batch
+    pub async fn get_meta<M>(
+        &mut self,
+        digest: &BatchHash<Tx>,
+    ) -> Result<Option<M>>
+    where
+        M: DeserializeOwned,
+    {
+        match self.store.read(Namespace::Meta.key(digest)).await? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+}
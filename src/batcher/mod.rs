@@ -1,12 +1,106 @@
-use crate::{Batch, Transaction};
+use crate::{tx_hash, Batch, BatchHeader, Config, Counter, Metrics, SealerParams, Transaction};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
 use std::pin::Pin;
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    oneshot, watch,
+};
+
+/// Makes the header of a batch, when it is sealed
+type HeaderFn = Box<dyn Fn() -> BatchHeader + Send>;
+
+/// Commands to drive a running batcher
+pub enum BatcherCommand {
+    /// The number of transactions waiting in the sealer
+    Pending(oneshot::Sender<usize>),
+    /// Seals the waiting transactions right away, and replies with their
+    /// number
+    Seal(oneshot::Sender<usize>),
+    /// Applies new parameters to the sealer
+    Reconfigure(SealerParams),
+}
+
+/// A handle to a running batcher
+#[derive(Clone)]
+pub struct BatcherHandle {
+    tx_command: UnboundedSender<BatcherCommand>,
+}
+
+impl BatcherHandle {
+    /// Returns the number of transactions waiting in the sealer
+    pub async fn pending(&self) -> Result<usize> {
+        self.ask(BatcherCommand::Pending).await
+    }
+
+    /// Seals the waiting transactions without waiting for the sealer, and
+    /// returns their number. Nothing is sealed if no transaction is waiting.
+    pub async fn seal(&self) -> Result<usize> {
+        self.ask(BatcherCommand::Seal).await
+    }
+
+    /// Applies new parameters to the sealer
+    pub fn reconfigure(
+        &self,
+        params: SealerParams,
+    ) -> Result<()> {
+        self.tx_command
+            .send(BatcherCommand::Reconfigure(params))
+            .map_err(|_| anyhow!("Batcher is shutting down"))
+    }
+
+    /// Applies the sealer parameters of every config seen by `rx_config`, until
+    /// the batcher shuts down
+    ///
+    /// The sealer is expected to be built from the current config: only later
+    /// changes are applied, so that starting to follow does not reset it.
+    pub fn follow<Round>(
+        &self,
+        mut rx_config: watch::Receiver<Config<Round>>,
+    ) where
+        Round: crate::Round,
+    {
+        let handle = self.clone();
+        tokio::spawn(async move {
+            let mut params = rx_config.borrow_and_update().sealer;
+            while rx_config.changed().await.is_ok() {
+                let new = rx_config.borrow_and_update().sealer;
+                if new == params {
+                    continue;
+                }
+                params = new;
+                if handle.reconfigure(params).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    async fn ask(
+        &self,
+        command: impl FnOnce(oneshot::Sender<usize>) -> BatcherCommand,
+    ) -> Result<usize> {
+        let (tx_reply, rx_reply) = oneshot::channel();
+        self.tx_command
+            .send(command(tx_reply))
+            .map_err(|_| anyhow!("Batcher is shutting down"))?;
+        Ok(rx_reply.await?)
+    }
+}
 
 /// The Batcher will collect transactions and make batches from them
 pub struct Batcher<Tx, Sealer> {
     rx_transaction: UnboundedReceiver<(Tx, usize)>,
     tx_output: UnboundedSender<Batch<Tx>>,
     sealer: Pin<Box<Sealer>>,
+    rx_command: UnboundedReceiver<BatcherCommand>,
+    /// The number of transactions in the sealer
+    pending: usize,
+    /// Makes the header of every sealed batch, if any
+    header: Option<HeaderFn>,
+    metrics: Metrics,
+    batches_sealed: Counter,
+    transactions_sealed: Counter,
 }
 
 impl<Tx, Sealer> Batcher<Tx, Sealer>
@@ -18,16 +112,58 @@ where
         rx_transaction: UnboundedReceiver<(Tx, usize)>,
         tx_output: UnboundedSender<Batch<Tx>>,
         sealer: Sealer,
-    ) {
+        metrics: Metrics,
+    ) -> BatcherHandle {
+        Self::start(rx_transaction, tx_output, sealer, None, metrics)
+    }
+
+    /// Like `spawn`, but stamps every batch with a header naming `author` (and
+    /// its `worker`) as the one that sealed it, in the latest round of
+    /// `rx_round`
+    pub fn spawn_with_header<Id, Round>(
+        rx_transaction: UnboundedReceiver<(Tx, usize)>,
+        tx_output: UnboundedSender<Batch<Tx>>,
+        sealer: Sealer,
+        author: Id,
+        worker: u32,
+        rx_round: watch::Receiver<Round>,
+        metrics: Metrics,
+    ) -> BatcherHandle
+    where
+        Id: Serialize + Send + Sync + 'static,
+        Round: Serialize + Send + Sync + 'static,
+    {
+        let header: HeaderFn =
+            Box::new(move || BatchHeader::new(&author, worker, &*rx_round.borrow()));
+        Self::start(rx_transaction, tx_output, sealer, Some(header), metrics)
+    }
+
+    fn start(
+        rx_transaction: UnboundedReceiver<(Tx, usize)>,
+        tx_output: UnboundedSender<Batch<Tx>>,
+        sealer: Sealer,
+        header: Option<HeaderFn>,
+        metrics: Metrics,
+    ) -> BatcherHandle {
+        let (tx_command, rx_command) = unbounded_channel();
+        let batches_sealed = metrics.batches_sealed(sealer.name());
+        let transactions_sealed = metrics.transactions_sealed(sealer.name());
         tokio::spawn(async move {
             Self {
                 rx_transaction,
                 tx_output,
                 sealer: Box::pin(sealer),
+                rx_command,
+                pending: 0,
+                header,
+                metrics,
+                batches_sealed,
+                transactions_sealed,
             }
             .run()
             .await
         });
+        BatcherHandle { tx_command }
     }
 
     async fn run(mut self) {
@@ -35,16 +171,69 @@ where
             tokio::select! {
                 Some((tx, tx_size)) = self.rx_transaction.recv() => {
                     log::debug!("Got a transaction");
+                    // Whoever sent it, the channel knows how many are left
+                    let queued = self.rx_transaction.len();
+                    self.metrics.transactions_queued.set(queued as i64);
                     self.sealer.as_mut().get_mut().update(tx, tx_size);
+                    self.pending += 1;
                 }
                 batch = (&mut self.sealer) => {
-                    if let Err(e) = self.tx_output.send(batch.into()) {
+                    if let Err(e) = self.output(batch) {
                         log::error!("Batcher Error: {}", e);
                         break;
                     }
                 }
+                Some(command) = self.rx_command.recv() => match command {
+                    BatcherCommand::Pending(tx_reply) => {
+                        let _ = tx_reply.send(self.pending);
+                    }
+                    BatcherCommand::Seal(tx_reply) => {
+                        let sealed = self.pending;
+                        if sealed > 0 {
+                            let batch = self.sealer.as_mut().get_mut().seal();
+                            if let Err(e) = self.output(batch) {
+                                log::error!("Batcher Error: {}", e);
+                                break;
+                            }
+                        }
+                        let _ = tx_reply.send(sealed);
+                    }
+                    BatcherCommand::Reconfigure(params) => {
+                        log::info!(
+                            "Sealer timeout: {} ms, size: {} B",
+                            params.timeout.as_millis(),
+                            params.size
+                        );
+                        self.sealer.as_mut().get_mut().reconfigure(&params);
+                    }
+                },
             }
         }
         log::info!("Batcher is shutting down!");
     }
+
+    /// Stamps a sealed batch and sends it out
+    fn output(
+        &mut self,
+        txs: Vec<Tx>,
+    ) -> Result<()> {
+        self.pending = 0;
+        let mut batch: Batch<Tx> = txs.into();
+        batch.header = self.header.as_ref().map(|header| header());
+        self.metrics.latency.sealed(&batch);
+        self.batches_sealed.inc();
+        self.transactions_sealed.inc_by(batch.payload.len() as u64);
+        tracing::debug!(
+            sealer = self.sealer.name(),
+            txs = batch.payload.len(),
+            "Sealed batch"
+        );
+        if tracing::enabled!(tracing::Level::TRACE) {
+            for tx in &batch.payload {
+                tracing::trace!(tx = %tx_hash(tx), "Sealed transaction");
+            }
+        }
+        self.tx_output.send(batch)?;
+        Ok(())
+    }
 }
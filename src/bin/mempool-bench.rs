@@ -0,0 +1,224 @@
+//! Sends transactions to the clients interface of mempool nodes at a target
+//! rate, and reports the rate achieved, e.g.
+//!
+//! ```text
+//! mempool-bench --committee committee.toml --rate 50000 --size 512 --duration 30
+//! ```
+//!
+//! One transaction of every burst is a sample. With `benchmark = true`, the
+//! nodes log a marker once the batch of a sample is stored. Given these logs
+//! with `--node-log`, the latency of the samples from their submission to the
+//! storage of their batch is reported too. This stops short of consensus: the
+//! time until commit is in the `stored_to_consensus` latency of the nodes.
+//! The clocks of the client and of the nodes must be in sync.
+//!
+//! Transactions are counted as sent once handed to the TCP sender, which only
+//! queues them: those lost with a broken connection are still counted, so the
+//! rates reported are the ones offered to the nodes.
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use libmempool_rs::{parse_sample_marker, unix_millis, Committee, RawTx, MIN_TX_SIZE};
+use std::{path::PathBuf, time::Duration};
+use tcp_sender::TcpSimpleSender;
+use tokio::time::{self, Instant};
+
+type Id = usize;
+
+const USAGE: &str = "Usage: mempool-bench --committee <FILE> [--nodes <ID,...>] \
+                     [--rate <TX/S>] [--size <BYTES>] [--duration <SECS>] \
+                     [--node-log <FILE>]...\n\n\
+                     --node-log reports the latency from submission to storage \
+                     (client_to_stored), not to commit by consensus.";
+
+/// How many bursts of transactions are sent every second
+const BURSTS_PER_SEC: u64 = 20;
+
+/// How long to wait for the last samples, before reading the logs of the nodes
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+struct Args {
+    committee: PathBuf,
+    /// The nodes to send transactions to. All of them if empty.
+    nodes: Vec<Id>,
+    /// Transactions per second
+    rate: u64,
+    /// The size of a transaction in bytes
+    size: usize,
+    duration: Duration,
+    /// The logs of the nodes, with the markers of the samples
+    node_logs: Vec<PathBuf>,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut committee = None;
+    let mut args = Args {
+        committee: PathBuf::new(),
+        nodes: Vec::new(),
+        rate: 1_000,
+        size: 512,
+        duration: Duration::from_secs(30),
+        node_logs: Vec::new(),
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        let value = argv
+            .next()
+            .ok_or_else(|| anyhow!("Missing the value of {}\n{}", arg, USAGE))?;
+        let invalid = || format!("Invalid value for {}: {}", arg, value);
+        match arg.as_str() {
+            "--committee" => committee = Some(PathBuf::from(&value)),
+            "--nodes" => {
+                args.nodes = value
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .with_context(invalid)?
+            }
+            "--rate" => args.rate = value.parse().with_context(invalid)?,
+            "--size" => args.size = value.parse().with_context(invalid)?,
+            "--duration" => {
+                args.duration = Duration::from_secs(value.parse().with_context(invalid)?)
+            }
+            "--node-log" => args.node_logs.push(PathBuf::from(&value)),
+            _ => return Err(anyhow!("Unexpected argument {}\n{}", arg, USAGE)),
+        }
+    }
+    args.committee = committee.ok_or_else(|| anyhow!(USAGE))?;
+    if args.rate == 0 {
+        return Err(anyhow!("The rate must be positive"));
+    }
+    if args.size < MIN_TX_SIZE {
+        return Err(anyhow!("Transactions must be at least {} bytes", MIN_TX_SIZE));
+    }
+    Ok(args)
+}
+
+/// Sends the transactions, and returns when every sample was sent
+async fn send(
+    args: &Args,
+    committee: &Committee<Id>,
+) -> Result<FnvHashMap<u64, u64>> {
+    let mut peers = committee.client_peers();
+    if !args.nodes.is_empty() {
+        for id in &args.nodes {
+            if !peers.contains_key(id) {
+                return Err(anyhow!("Node {} is not in the committee", id));
+            }
+        }
+        peers.retain(|id, _| args.nodes.contains(id));
+    }
+    let mut targets: Vec<Id> = peers.keys().copied().collect();
+    targets.sort_unstable();
+    let mut sender = TcpSimpleSender::<Id, RawTx>::with_peers(peers);
+
+    // Spread the transactions of a second over its bursts
+    let burst = (args.rate / BURSTS_PER_SEC).max(1);
+    let mut interval = time::interval(Duration::from_millis(1_000 / BURSTS_PER_SEC));
+    let mut samples = FnvHashMap::default();
+    let mut counter = 0;
+    let mut sent = 0u64;
+    let mut failed = 0u64;
+    let mut warned = false;
+    let start = Instant::now();
+    log::info!(
+        "Sending {} transactions of {} bytes per second to nodes {:?}",
+        burst * BURSTS_PER_SEC,
+        args.size,
+        targets
+    );
+
+    for sample in 0.. {
+        interval.tick().await;
+        if start.elapsed() >= args.duration {
+            break;
+        }
+        let burst_start = Instant::now();
+        for i in 0..burst {
+            let tx = if i == 0 {
+                samples.insert(sample, unix_millis());
+                RawTx::sample(sample, args.size)
+            } else {
+                RawTx::standard(counter, args.size)
+            };
+            let target = targets[(counter % targets.len() as u64) as usize];
+            counter += 1;
+            // Only queues the transaction, so `Ok` does not mean it reached the node
+            match sender.send(target, Bytes::from(bincode::serialize(&tx)?)).await {
+                Ok(()) => sent += 1,
+                Err(e) => {
+                    log::debug!("Failed to send a transaction to node {}: {}", target, e);
+                    // A sample that was not sent is never stored
+                    if i == 0 {
+                        samples.remove(&sample);
+                    }
+                    failed += 1;
+                }
+            }
+        }
+        if !warned && burst_start.elapsed() > Duration::from_millis(1_000 / BURSTS_PER_SEC) {
+            log::warn!("The transaction rate is too high for this client");
+            warned = true;
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    log::info!("Queued {} transactions in {:.1} s, {} failed", sent, elapsed, failed);
+    if failed > 0 {
+        log::warn!("{} of the {} transactions could not be sent", failed, counter);
+    }
+    // NOTE: These log entries are used to compute performance.
+    log::info!("Bench client_rate: {:.0} tx/s", sent as f64 / elapsed);
+    log::info!(
+        "Bench client_throughput: {:.0} B/s",
+        (sent as f64 * args.size as f64) / elapsed
+    );
+    Ok(samples)
+}
+
+/// Reports the time from the submission of the samples to their storage, with
+/// the markers found in the logs of the nodes
+fn report_latency(
+    samples: &FnvHashMap<u64, u64>,
+    node_logs: &[PathBuf],
+) -> Result<()> {
+    let mut latencies = Vec::new();
+    for path in node_logs {
+        let log = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for (id, ready) in log.lines().filter_map(parse_sample_marker) {
+            if let Some(sent) = samples.get(&id) {
+                latencies.push(ready.saturating_sub(*sent));
+            }
+        }
+    }
+    if latencies.is_empty() {
+        log::warn!("No sample marker found in the logs. Do the nodes set `benchmark = true`?");
+        return Ok(());
+    }
+    let mean = latencies.iter().sum::<u64>() / latencies.len() as u64;
+    log::info!("{} of the {} samples are stored", latencies.len(), samples.len());
+    // NOTE: These log entries are used to compute performance.
+    log::info!("Bench client_to_stored: {} ms", mean);
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args = parse_args()?;
+    let committee = Committee::<Id>::load(&args.committee)?;
+    committee.validate()?;
+    let samples = send(&args, &committee).await?;
+    if !args.node_logs.is_empty() {
+        time::sleep(SETTLE_TIME).await;
+        report_latency(&samples, &args.node_logs)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Runs a mempool node on its own, e.g.
+//!
+//! ```text
+//! mempool-node --committee committee.toml --config node.toml
+//! ```
+//!
+//! See `Committee` and `NodeConfig` for the files, and `serve_consensus` for
+//! the interface to consensus.
+
+use anyhow::{anyhow, Result};
+use libmempool_rs::{Committee, MempoolNode, NodeConfig, RawTx};
+use libstorage::rocksdb::Storage;
+use std::path::PathBuf;
+
+type Id = usize;
+type Round = u64;
+
+const USAGE: &str = "Usage: mempool-node --committee <FILE> --config <FILE>";
+
+/// Returns the paths of the committee and of the node config
+fn parse_args() -> Result<(PathBuf, PathBuf)> {
+    let mut committee = None;
+    let mut config = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let slot = match arg.as_str() {
+            "--committee" => &mut committee,
+            "--config" => &mut config,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => return Err(anyhow!("Unexpected argument {}\n{}", arg, USAGE)),
+        };
+        *slot = Some(PathBuf::from(args.next().ok_or_else(|| anyhow!(USAGE))?));
+    }
+    match (committee, config) {
+        (Some(committee), Some(config)) => Ok((committee, config)),
+        _ => Err(anyhow!(USAGE)),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let (committee, config) = parse_args()?;
+    let committee = Committee::<Id>::load(committee)?;
+    let config = NodeConfig::<Id, Round>::load(config)?;
+    let path = config.store_path.to_string_lossy().to_string();
+    let store = Storage::new(&path)?;
+
+    let _node = MempoolNode::<Round, _, RawTx>::spawn(config, committee, store).await?;
+    log::info!("Mempool node is running");
+    std::future::pending::<()>().await;
+    Ok(())
+}
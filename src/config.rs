@@ -1,17 +1,180 @@
+use std::net::SocketAddr;
 use std::time::Duration;
 
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// (De)serializes durations as a number of milliseconds. Configs serialized
+/// before are also accepted, with durations in the `{ secs, nanos }` form of
+/// serde.
+///
+/// Telling both forms apart takes a self-describing format (e.g., JSON or
+/// TOML): configs serialized with bincode cannot be deserialized.
+mod millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    /// The forms a duration may take
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    pub enum Millis {
+        Millis(u64),
+        Legacy(Duration),
+    }
+
+    impl From<Millis> for Duration {
+        fn from(millis: Millis) -> Self {
+            match millis {
+                Millis::Millis(ms) => Duration::from_millis(ms),
+                Millis::Legacy(duration) => duration,
+            }
+        }
+    }
+
+    pub fn serialize<S>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Millis::deserialize(deserializer).map(Duration::from)
+    }
+
+    /// The same, for optional durations
+    pub mod option {
+        use super::Millis;
+        use serde::{Deserialize, Deserializer, Serializer};
+        use std::time::Duration;
+
+        pub fn serialize<S>(
+            duration: &Option<Duration>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match duration {
+                Some(duration) => serializer.serialize_some(&(duration.as_millis() as u64)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Option::<Millis>::deserialize(deserializer).map(|ms| ms.map(Duration::from))
+        }
+    }
+}
+
+/// The parameters of the sealers, used by the batcher
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SealerParams {
+    /// The time after which a batch is sealed, whatever its size. Denominated
+    /// in ms, defaults to 100.
+    #[serde(with = "millis")]
+    pub timeout: Duration,
+    /// The size after which a batch is sealed, whatever its age. Denominated
+    /// in bytes, defaults to 500 KB.
+    pub size: usize,
+}
+
+impl Default for SealerParams {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(100),
+            size: 500_000,
+        }
+    }
+}
+
+/// The parameters of the mempool
+///
+/// Missing fields take their default value when deserializing, and durations
+/// are denominated in ms. Only self-describing formats (e.g., JSON or TOML)
+/// can deserialize it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, bound(deserialize = "Round: crate::Round + Deserialize<'de>"))]
 pub struct Config<Round> {
     /// The depth of the garbage collection (Denominated in number of rounds).
+    /// Defaults to `Round::DEFAULT_GC_DEPTH`, i.e., 50 for the integer rounds.
+    /// A depth of `Round::MIN` is rejected, as it would collect batches as
+    /// soon as they are stored.
     pub gc_depth: Round,
     /// The delay after which the synchronizer retries to send sync requests.
-    /// Denominated in ms.
+    /// Denominated in ms, defaults to 100.
+    #[serde(with = "millis")]
     pub sync_retry_delay: Duration,
+    /// The maximum delay between two sync retries. The delay doubles on every
+    /// retry until it reaches this value. Denominated in ms, defaults to 5000.
+    #[serde(with = "millis")]
+    pub sync_retry_max_delay: Duration,
+    /// The number of sync retries after which we give up on a batch. Defaults
+    /// to 10.
+    pub sync_retry_max_attempts: usize,
     /// Determine with how many nodes to sync when re-trying to send
-    /// sync-request. These nodes are picked at random from the committee.
+    /// sync-request. These nodes are picked at random from the committee,
+    /// among the ones we did not ask yet. Defaults to 3.
     pub sync_retry_nodes: usize,
+    /// Batches larger than this are streamed to other nodes in chunks of this
+    /// size. Denominated in bytes, defaults to 1 MiB.
+    pub sync_chunk_size: usize,
+    /// The number of chunks sent before waiting for the requester to ask for
+    /// more. Defaults to 4.
+    pub sync_chunk_window: usize,
+    /// The maximum number of bytes sent in response to a single sync request.
+    /// Defaults to 16 MiB.
+    pub sync_max_bytes_per_request: usize,
+    /// The number of sync requests from other nodes that are served
+    /// concurrently. Defaults to 8.
+    pub sync_helper_workers: usize,
+    /// The largest batch that we reassemble from the chunks of other nodes.
+    /// Denominated in bytes, defaults to 16 MiB.
+    pub max_batch_size: usize,
+    /// How often the stored batches are checked against their digests. `None`
+    /// disables this. Denominated in ms, defaults to 10 minutes.
+    #[serde(with = "millis::option")]
+    pub scrub_interval: Option<Duration>,
+    /// The number of rounds after which the transactions of a batch that was
+    /// not committed are fed back to the batcher. `None` (the default)
+    /// disables this. Batches are re-injected when they are garbage collected
+    /// at the latest, even if this is deeper than `gc_depth`.
+    pub reinject_depth: Option<Round>,
+    /// Where the metrics are served over HTTP, in the Prometheus text format.
+    /// It must be a loopback address. `None` (the default) disables this.
+    pub metrics_addr: Option<SocketAddr>,
+    /// The parameters of the sealer.
+    pub sealer: SealerParams,
+}
+
+/// Logs the fields that differ between two configs, and counts them
+macro_rules! log_changes {
+    ($old: expr, $new: expr, $($field: ident),+ $(,)?) => {{
+        let mut changes = 0;
+        $(
+            if $old.$field != $new.$field {
+                log::info!(
+                    "Config: {} changed from {:?} to {:?}",
+                    stringify!($field),
+                    $old.$field,
+                    $new.$field
+                );
+                changes += 1;
+            }
+        )+
+        changes
+    }};
 }
 
 impl<Round> Config<Round>
@@ -21,7 +184,189 @@ where
     pub fn log(&self) {
         log::info!("GC Depth: {}", self.gc_depth);
         log::info!("Sync retry delay: {} ms", self.sync_retry_delay.as_millis());
+        log::info!("Sync retry max delay: {} ms", self.sync_retry_max_delay.as_millis());
+        log::info!("Sync retry max attempts: {}", self.sync_retry_max_attempts);
         log::info!("Sync retry nodes: {}", self.sync_retry_nodes);
+        log::info!("Sync chunk size: {} B", self.sync_chunk_size);
+        log::info!("Sync chunk window: {}", self.sync_chunk_window);
+        log::info!("Sync max bytes per request: {} B", self.sync_max_bytes_per_request);
+        log::info!("Sync helper workers: {}", self.sync_helper_workers);
+        log::info!("Max batch size: {} B", self.max_batch_size);
+        match self.scrub_interval {
+            Some(interval) => log::info!("Scrub interval: {} ms", interval.as_millis()),
+            None => log::info!("Scrub interval: disabled"),
+        }
+        match self.reinject_depth {
+            Some(depth) => log::info!("Re-inject depth: {}", depth),
+            None => log::info!("Re-inject depth: disabled"),
+        }
+        match self.metrics_addr {
+            Some(addr) => log::info!("Metrics address: {}", addr),
+            None => log::info!("Metrics address: disabled"),
+        }
+        log::info!("Sealer timeout: {} ms", self.sealer.timeout.as_millis());
+        log::info!("Sealer size: {} B", self.sealer.size);
+    }
+
+    /// Checks that the parameters make sense
+    pub fn validate(&self) -> Result<()> {
+        if self.gc_depth == Round::MIN {
+            return Err(anyhow!("The gc depth must be set, and larger than {}", Round::MIN));
+        }
+        if self.reinject_depth == Some(Round::MIN) {
+            return Err(anyhow!("The re-inject depth must be larger than {}", Round::MIN));
+        }
+        if let Some(addr) = self.metrics_addr {
+            if !addr.ip().is_loopback() {
+                return Err(anyhow!("The metrics address {} is not a loopback address", addr));
+            }
+        }
+        if self.sync_retry_delay.is_zero() {
+            return Err(anyhow!("The sync retry delay must be positive"));
+        }
+        if self.sync_retry_max_delay < self.sync_retry_delay {
+            return Err(anyhow!(
+                "The sync retry max delay ({} ms) is shorter than the sync retry delay ({} ms)",
+                self.sync_retry_max_delay.as_millis(),
+                self.sync_retry_delay.as_millis()
+            ));
+        }
+        if self.sync_retry_nodes == 0 {
+            return Err(anyhow!("Sync retries must go to at least one node"));
+        }
+        if self.sync_chunk_size == 0 || self.sync_chunk_window == 0 {
+            return Err(anyhow!("The sync chunk size and window must be positive"));
+        }
+        if self.sync_max_bytes_per_request == 0 {
+            return Err(anyhow!("The sync max bytes per request must be positive"));
+        }
+        if self.sync_helper_workers == 0 {
+            return Err(anyhow!("At least one sync helper worker is needed"));
+        }
+        // A batch is streamed within the byte budget of a single request
+        if self.max_batch_size > self.sync_max_bytes_per_request {
+            return Err(anyhow!(
+                "The max batch size ({} B) exceeds the sync max bytes per request ({} B)",
+                self.max_batch_size,
+                self.sync_max_bytes_per_request
+            ));
+        }
+        if self.scrub_interval.is_some_and(|interval| interval.is_zero()) {
+            return Err(anyhow!("The scrub interval must be positive, or disabled"));
+        }
+        if self.sealer.timeout.is_zero() || self.sealer.size == 0 {
+            return Err(anyhow!("The sealer timeout and size must be positive"));
+        }
+        if self.sealer.size > self.max_batch_size {
+            return Err(anyhow!(
+                "The sealer size ({} B) exceeds the max batch size ({} B)",
+                self.sealer.size,
+                self.max_batch_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Logs the parameters that differ from `old`, and returns their number
+    pub fn log_changes(
+        &self,
+        old: &Self,
+    ) -> usize {
+        log_changes!(
+            old,
+            self,
+            gc_depth,
+            sync_retry_delay,
+            sync_retry_max_delay,
+            sync_retry_max_attempts,
+            sync_retry_nodes,
+            sync_chunk_size,
+            sync_chunk_window,
+            sync_max_bytes_per_request,
+            sync_helper_workers,
+            max_batch_size,
+            scrub_interval,
+            reinject_depth,
+            metrics_addr,
+            sealer,
+        )
+    }
+
+    /// Fails if `self` changes a parameter that is fixed once the mempool is
+    /// spawned
+    fn check_fixed(
+        &self,
+        old: &Self,
+    ) -> Result<()> {
+        let fixed = [
+            ("sync_chunk_size", self.sync_chunk_size != old.sync_chunk_size),
+            ("sync_chunk_window", self.sync_chunk_window != old.sync_chunk_window),
+            (
+                "sync_max_bytes_per_request",
+                self.sync_max_bytes_per_request != old.sync_max_bytes_per_request,
+            ),
+            ("sync_helper_workers", self.sync_helper_workers != old.sync_helper_workers),
+            ("max_batch_size", self.max_batch_size != old.max_batch_size),
+            ("scrub_interval", self.scrub_interval != old.scrub_interval),
+            ("metrics_addr", self.metrics_addr != old.metrics_addr),
+        ];
+        match fixed.iter().find(|(_, changed)| *changed) {
+            Some((name, _)) => Err(anyhow!("{} cannot be changed at runtime", name)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Updates the config of a running mempool
+///
+/// The synchronizer and the batcher follow the config through the receivers
+/// of `subscribe`. Only the gc depth, the sync retry parameters, the re-inject
+/// depth and the sealer parameters can change at runtime.
+pub struct ConfigUpdater<Round> {
+    tx_config: watch::Sender<Config<Round>>,
+}
+
+impl<Round> ConfigUpdater<Round>
+where
+    Round: crate::Round,
+{
+    pub fn new(config: Config<Round>) -> Result<Self> {
+        config.validate()?;
+        let (tx_config, _) = watch::channel(config);
+        Ok(Self { tx_config })
+    }
+
+    /// Returns a receiver that sees every update
+    pub fn subscribe(&self) -> watch::Receiver<Config<Round>> {
+        self.tx_config.subscribe()
+    }
+
+    /// Returns the config in effect
+    pub fn current(&self) -> Config<Round> {
+        self.tx_config.borrow().clone()
+    }
+
+    /// Validates `config`, logs how it differs from the config in effect, and
+    /// hands it to the subscribers. Returns the number of changed parameters.
+    pub fn update(
+        &self,
+        config: Config<Round>,
+    ) -> Result<usize> {
+        config.validate()?;
+        let mut result = Ok(0);
+        self.tx_config.send_if_modified(|current| {
+            result = config
+                .check_fixed(current)
+                .map(|()| config.log_changes(current));
+            match result {
+                Ok(changes) if changes > 0 => {
+                    *current = config.clone();
+                    true
+                }
+                _ => false,
+            }
+        });
+        result
     }
 }
 
@@ -31,9 +376,20 @@ where
 {
     fn default() -> Self {
         Self {
-            gc_depth: Default::default(),
+            gc_depth: Round::DEFAULT_GC_DEPTH,
             sync_retry_delay: Duration::from_millis(100),
+            sync_retry_max_delay: Duration::from_millis(5_000),
+            sync_retry_max_attempts: 10,
             sync_retry_nodes: 3,
+            sync_chunk_size: 1 << 20,
+            sync_chunk_window: 4,
+            sync_max_bytes_per_request: 16 << 20,
+            sync_helper_workers: 8,
+            max_batch_size: 16 << 20,
+            scrub_interval: Some(Duration::from_secs(600)),
+            reinject_depth: None,
+            metrics_addr: None,
+            sealer: SealerParams::default(),
         }
     }
 }
@@ -1,37 +1,117 @@
-use crate::{Batch, BatchHash, MempoolMsg, Transaction};
+use crate::{
+    reassembler::STREAM_TIMEOUT, scrubber::quarantine, BatchChunk, BatchHash, BatchStore,
+    MempoolMsg, MempoolNetwork, Metrics, Transaction,
+};
 use bytes::Bytes;
+use fnv::{FnvHashMap, FnvHashSet};
+use futures::{stream::FuturesUnordered, StreamExt};
 use serde::Serialize;
+use std::collections::VecDeque;
 use std::fmt::Debug;
 use tcp_sender::TcpSimpleSender;
-use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::{
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+    time::Instant,
+};
+use tracing::Instrument;
+
+/// The number of requests we queue per peer, before dropping new ones
+const MAX_QUEUED_REQUESTS: usize = 64;
+
+/// A sync request from another mempool
+#[derive(Debug)]
+pub enum HelperRequest<Id, Tx> {
+    /// Send these batches
+    Batches(Id, Vec<BatchHash<Tx>>),
+    /// Send the rest of this batch, starting at the given offset
+    Chunks(Id, BatchHash<Tx>, u64),
+}
+
+impl<Id, Tx> HelperRequest<Id, Tx> {
+    /// The mempool that sent this request
+    pub fn source(&self) -> &Id {
+        match self {
+            HelperRequest::Batches(source, _) => source,
+            HelperRequest::Chunks(source, _, _) => source,
+        }
+    }
+}
 
 /// The responsibility of this struct is to help other mempools by responding to
 /// their sync requests
-pub struct Helper<Id, Storage, Tx>
+///
+/// Requests are served concurrently by (at most) `num_workers` workers, which
+/// send the responses themselves. Each peer has at most one request being
+/// served at a time, and the peers take turns, so that one (slow or greedy)
+/// peer does not stall the others.
+///
+/// A batch streamed in chunks is sent within the byte budget of a single
+/// request, resumes included.
+pub struct Helper<Id, Storage, Tx, Net = TcpSimpleSender<Id, MempoolMsg<Id, Tx>>>
 where
     Tx: Transaction,
 {
-    mempool_sender: TcpSimpleSender<Id, MempoolMsg<Id, Tx>>,
-    rx_request: UnboundedReceiver<(Id, Vec<BatchHash<Tx>>)>,
-    store: Storage,
+    /// Forked for every peer we serve
+    mempool_sender: Net,
+    /// The senders of the workers, one per peer. The worker serving a peer
+    /// borrows its sender.
+    senders: FnvHashMap<Id, Net>,
+    rx_request: UnboundedReceiver<HelperRequest<Id, Tx>>,
+    responder: Responder<Id, Storage, Tx>,
+    num_workers: usize,
+    /// The requests waiting to be served, per peer
+    queues: FnvHashMap<Id, VecDeque<HelperRequest<Id, Tx>>>,
+    /// The peers with queued requests, in the order they will be served
+    rotation: VecDeque<Id>,
+    /// The peers with a request being served
+    busy: FnvHashSet<Id>,
+    /// The bytes of the batches streamed to every peer so far, and when we
+    /// last sent some
+    streamed: FnvHashMap<(Id, BatchHash<Tx>), (usize, Instant)>,
+    metrics: Metrics,
 }
 
-impl<Id, Storage, Tx> Helper<Id, Storage, Tx>
+impl<Id, Storage, Tx, Net> Helper<Id, Storage, Tx, Net>
 where
     Id: Debug + Clone + Eq + std::hash::Hash + Send + Sync + Serialize + 'static,
     Storage: libstorage::Store,
     Tx: Transaction,
+    Net: MempoolNetwork<Id>,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
-        mempool_sender: TcpSimpleSender<Id, MempoolMsg<Id, Tx>>,
-        rx_request: UnboundedReceiver<(Id, Vec<BatchHash<Tx>>)>,
-        store: Storage,
+        my_name: Id,
+        mempool_sender: Net,
+        rx_request: UnboundedReceiver<HelperRequest<Id, Tx>>,
+        store: BatchStore<Storage, Tx>,
+        chunk_size: usize,
+        chunk_window: usize,
+        max_bytes_per_request: usize,
+        num_workers: usize,
+        // Corrupted batches are sent to the synchronizer to be fetched again
+        tx_resync: UnboundedSender<BatchHash<Tx>>,
+        metrics: Metrics,
     ) {
         tokio::spawn(async move {
             Self {
                 mempool_sender,
+                senders: FnvHashMap::default(),
                 rx_request,
-                store,
+                responder: Responder {
+                    my_name,
+                    store,
+                    chunk_size,
+                    chunk_window,
+                    max_bytes_per_request,
+                    tx_resync,
+                    metrics: metrics.clone(),
+                },
+                num_workers: num_workers.max(1),
+                queues: FnvHashMap::default(),
+                rotation: VecDeque::new(),
+                busy: FnvHashSet::default(),
+                streamed: FnvHashMap::default(),
+                metrics,
             }
             .run()
             .await
@@ -39,22 +119,298 @@ where
     }
 
     async fn run(&mut self) {
-        while let Some((source, digests)) = self.rx_request.recv().await {
-            for digest in digests {
-                match self.store.read(digest.to_vec()).await {
-                    Ok(Some(data)) => {
-                        let b: Batch<Tx> = bincode::deserialize(&data).unwrap();
-                        let msg: MempoolMsg<Id, Tx> = MempoolMsg::Batch(b);
-                        let serialized = Bytes::from(bincode::serialize(&msg).unwrap());
-                        if let Err(e) = self.mempool_sender.send(source.clone(), serialized).await {
-                            log::warn!("Helper send error: {}", e);
+        let mut workers = FuturesUnordered::new();
+        loop {
+            while workers.len() < self.num_workers {
+                let (source, request) = match self.next_request() {
+                    Some(next) => next,
+                    None => break,
+                };
+                let span = match &request {
+                    HelperRequest::Batches(_, digests) => {
+                        tracing::debug_span!("serve_batches", source = ?source, batches = ?digests)
+                    }
+                    HelperRequest::Chunks(_, digest, offset) => {
+                        tracing::debug_span!("serve_chunks", source = ?source, batch = %digest, offset)
+                    }
+                };
+                let served = match &request {
+                    HelperRequest::Chunks(_, digest, _) => self
+                        .streamed
+                        .get(&(source.clone(), digest.clone()))
+                        .map_or(0, |(sent, _)| *sent),
+                    HelperRequest::Batches(..) => 0,
+                };
+                let responder = self.responder.clone();
+                let mut sender = match self.senders.remove(&source) {
+                    Some(sender) => sender,
+                    None => self.mempool_sender.fork(),
+                };
+                workers.push(tokio::spawn(
+                    async move {
+                        let streamed = responder.respond(request, served, &mut sender).await;
+                        (source, sender, streamed)
+                    }
+                    .instrument(span),
+                ));
+            }
+
+            tokio::select! {
+                Some(request) = self.rx_request.recv() => self.enqueue(request),
+                Some(result) = workers.next() => match result {
+                    Ok((source, sender, streamed)) => {
+                        self.busy.remove(&source);
+                        self.metrics.sync_requests_served.inc();
+                        self.record_streamed(&source, streamed);
+                        self.senders.insert(source, sender);
+                    }
+                    Err(e) => log::error!("Helper worker failed: {}", e),
+                },
+                else => break,
+            }
+        }
+        log::warn!("Helper is quitting");
+    }
+
+    /// Adds up the bytes streamed to `source`, and forgets about the streams
+    /// that are complete or idle
+    fn record_streamed(
+        &mut self,
+        source: &Id,
+        streamed: Vec<(BatchHash<Tx>, usize, bool)>,
+    ) {
+        self.streamed
+            .retain(|_, (_, last_sent)| last_sent.elapsed() < STREAM_TIMEOUT);
+        for (digest, sent, complete) in streamed {
+            let key = (source.clone(), digest);
+            if complete {
+                self.streamed.remove(&key);
+                continue;
+            }
+            let (total, last_sent) = self.streamed.entry(key).or_insert((0, Instant::now()));
+            *total += sent;
+            *last_sent = Instant::now();
+        }
+    }
+
+    fn enqueue(
+        &mut self,
+        request: HelperRequest<Id, Tx>,
+    ) {
+        let source = request.source().clone();
+        let queue = self.queues.entry(source.clone()).or_default();
+        if queue.len() >= MAX_QUEUED_REQUESTS {
+            log::debug!("Too many queued requests from {:?}, dropping", source);
+            self.metrics.sync_requests_dropped.inc();
+            return;
+        }
+        self.metrics.sync_requests_queued.inc();
+        if queue.is_empty() {
+            self.rotation.push_back(source);
+        }
+        queue.push_back(request);
+    }
+
+    /// Picks the next request to serve, from the next peer (in turn) that is
+    /// not being served already
+    fn next_request(&mut self) -> Option<(Id, HelperRequest<Id, Tx>)> {
+        for _ in 0..self.rotation.len() {
+            let peer = self.rotation.pop_front()?;
+            if self.busy.contains(&peer) {
+                self.rotation.push_back(peer);
+                continue;
+            }
+            let queue = self.queues.get_mut(&peer)?;
+            let request = queue.pop_front()?;
+            if queue.is_empty() {
+                self.queues.remove(&peer);
+            } else {
+                self.rotation.push_back(peer.clone());
+            }
+            self.busy.insert(peer.clone());
+            self.metrics.sync_requests_queued.dec();
+            return Some((peer, request));
+        }
+        None
+    }
+}
+
+/// Answers a sync request, from the stored batches
+struct Responder<Id, Storage, Tx> {
+    my_name: Id,
+    store: BatchStore<Storage, Tx>,
+    /// Batches larger than this are streamed in chunks of this size
+    chunk_size: usize,
+    /// The number of chunks we send before waiting for the requester to ask
+    /// for more
+    chunk_window: usize,
+    /// The maximum number of bytes we send in response to a single request,
+    /// including the resumes of a stream
+    max_bytes_per_request: usize,
+    /// Used to fetch corrupted batches again from other nodes
+    tx_resync: UnboundedSender<BatchHash<Tx>>,
+    metrics: Metrics,
+}
+
+impl<Id, Storage, Tx> Clone for Responder<Id, Storage, Tx>
+where
+    Id: Clone,
+    Storage: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            my_name: self.my_name.clone(),
+            store: self.store.clone(),
+            chunk_size: self.chunk_size,
+            chunk_window: self.chunk_window,
+            max_bytes_per_request: self.max_bytes_per_request,
+            tx_resync: self.tx_resync.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+impl<Id, Storage, Tx> Responder<Id, Storage, Tx>
+where
+    Id: Debug + Clone + Eq + std::hash::Hash + Send + Sync + Serialize + 'static,
+    Storage: libstorage::Store,
+    Tx: Transaction,
+{
+    /// Sends the response to a request, batch after batch and chunk after
+    /// chunk, to keep a single batch in memory. `libstorage::Store` has no
+    /// multi-get, so the batches are read one at a time. A resumed stream gets
+    /// what is left of its budget after the `served` bytes. Returns the bytes
+    /// of every batch streamed in chunks, with whether it was sent to the end.
+    async fn respond<Net>(
+        self,
+        request: HelperRequest<Id, Tx>,
+        served: usize,
+        sender: &mut Net,
+    ) -> Vec<(BatchHash<Tx>, usize, bool)>
+    where
+        Net: MempoolNetwork<Id>,
+    {
+        let mut store = self.store.clone();
+        let mut streamed = Vec::new();
+        match request {
+            HelperRequest::Batches(source, digests) => {
+                let mut budget = self.max_bytes_per_request;
+                for digest in digests {
+                    if budget == 0 {
+                        log::debug!("Request from {:?} exceeds the byte limit", source);
+                        break;
+                    }
+                    match store.get_raw(&digest).await {
+                        Ok(Some(data)) if !self.verify(&digest, &data).await => {}
+                        Ok(Some(data)) if data.len() > self.chunk_size => {
+                            let sent = self
+                                .send_chunks(sender, &source, digest.clone(), &data, 0, budget)
+                                .await;
+                            budget = budget.saturating_sub(sent);
+                            streamed.push((digest, sent, sent >= data.len()));
                         }
+                        Ok(Some(data)) => {
+                            budget = budget.saturating_sub(data.len());
+                            let message = MempoolMsg::<Id, Tx>::frame_batch(&data);
+                            self.send(sender, &source, message).await;
+                        }
+                        Ok(None) => log::debug!("Digest: {} not found", digest),
+                        Err(e) => log::warn!("Store Error: {}", e),
+                    }
+                }
+            }
+            HelperRequest::Chunks(source, digest, offset) => {
+                let budget = self.max_bytes_per_request.saturating_sub(served);
+                if budget == 0 {
+                    log::debug!("Stream of {} to {:?} exceeds the byte limit", digest, source);
+                    return streamed;
+                }
+                match store.get_raw(&digest).await {
+                    Ok(Some(data)) if !self.verify(&digest, &data).await => {}
+                    Ok(Some(data)) => {
+                        let offset = offset as usize;
+                        let sent = self
+                            .send_chunks(sender, &source, digest.clone(), &data, offset, budget)
+                            .await;
+                        streamed.push((digest, sent, offset + sent >= data.len()));
                     }
                     Ok(None) => log::debug!("Digest: {} not found", digest),
                     Err(e) => log::warn!("Store Error: {}", e),
                 }
             }
         }
-        log::warn!("Helper is quitting");
+        streamed
+    }
+
+    /// Checks that a stored batch still hashes to its digest. Corrupted batches
+    /// are quarantined, and synchronized again from other nodes.
+    async fn verify(
+        &self,
+        digest: &BatchHash<Tx>,
+        data: &[u8],
+    ) -> bool {
+        let hash = BatchStore::<Storage, Tx>::digest(data);
+        if &hash == digest {
+            return true;
+        }
+
+        self.metrics.batches_corrupted.inc();
+        let count = self.metrics.batches_corrupted.get();
+        log::error!("Stored batch {} is corrupted ({} so far), re-syncing it", digest, count);
+        let mut store = self.store.clone();
+        quarantine(&mut store, digest, data.to_vec(), &self.tx_resync).await;
+        false
+    }
+
+    async fn send<Net>(
+        &self,
+        sender: &mut Net,
+        recipient: &Id,
+        message: Bytes,
+    ) where
+        Net: MempoolNetwork<Id>,
+    {
+        self.metrics.sync_bytes_served.inc_by(message.len() as u64);
+        if let Err(e) = sender.send(recipient.clone(), message).await {
+            log::warn!("Helper send error: {}", e);
+        }
+    }
+
+    /// Sends (at most) a window of chunks of a serialized batch, starting at
+    /// `offset`, without exceeding `budget` bytes. Returns the number of bytes
+    /// sent.
+    async fn send_chunks<Net>(
+        &self,
+        sender: &mut Net,
+        recipient: &Id,
+        digest: BatchHash<Tx>,
+        data: &[u8],
+        mut offset: usize,
+        budget: usize,
+    ) -> usize
+    where
+        Net: MempoolNetwork<Id>,
+    {
+        let mut sent = 0;
+        for i in 0..self.chunk_window {
+            if offset >= data.len() || sent >= budget {
+                break;
+            }
+            let end = (offset + self.chunk_size).min(data.len());
+            let len = end - offset;
+            let chunk = BatchChunk {
+                digest: digest.clone(),
+                offset: offset as u64,
+                total: data.len() as u64,
+                window_end: i + 1 == self.chunk_window || end == data.len() || sent + len >= budget,
+                data: data[offset..end].to_vec(),
+            };
+            let message = MempoolMsg::BatchChunk(self.my_name.clone(), chunk);
+            self.send(sender, recipient, Bytes::from(bincode::serialize(&message).unwrap()))
+                .await;
+            sent += len;
+            offset = end;
+        }
+        sent
     }
 }
@@ -1,22 +1,52 @@
+mod admin;
+mod batch_store;
 pub mod batcher;
 mod config;
 mod helper;
+#[cfg(any(test, feature = "memory-store"))]
+mod memory_store;
 mod mempool;
 mod mempool_handler;
+mod metrics;
 mod msg;
+mod network;
+#[cfg(feature = "node")]
+mod node;
+#[cfg(feature = "node")]
+mod node_config;
 mod processor;
+mod query;
 pub mod quorum_waiter;
+mod reassembler;
+mod scrubber;
 pub mod sealer;
+#[cfg(any(test, feature = "sim"))]
+mod sim;
 mod synchronizer;
 mod traits;
 mod tx_handler;
 
+pub use admin::*;
+pub use batch_store::*;
 pub use config::*;
 pub use helper::*;
+#[cfg(any(test, feature = "memory-store"))]
+pub use memory_store::*;
 pub use mempool::*;
 pub use mempool_handler::*;
+pub use metrics::*;
 pub use msg::*;
+pub use network::*;
+#[cfg(feature = "node")]
+pub use node::*;
+#[cfg(feature = "node")]
+pub use node_config::*;
 pub use processor::*;
+pub use query::*;
+pub use reassembler::*;
+pub use scrubber::*;
+#[cfg(any(test, feature = "sim"))]
+pub use sim::*;
 pub use synchronizer::*;
 pub use traits::*;
 pub use tx_handler::*;
@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+use fnv::FnvHashMap;
+use libstorage::{Store, StoreResult};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+type Key = Vec<u8>;
+type Value = Vec<u8>;
+
+#[derive(Default)]
+struct Inner {
+    /// The stored values
+    values: FnvHashMap<Key, Value>,
+    /// The `notify_read` calls waiting for a key to be written
+    waiting: FnvHashMap<Key, Vec<oneshot::Sender<Value>>>,
+}
+
+impl Inner {
+    /// Drops the `notify_read` calls that were given up on, e.g., cancelled by
+    /// a timeout, so that keys that are never written do not leak
+    fn prune(&mut self) {
+        self.waiting.retain(|_, waiting| {
+            waiting.retain(|tx_value| !tx_value.is_closed());
+            !waiting.is_empty()
+        });
+    }
+}
+
+/// A `libstorage::Store` that keeps everything in memory, for tests and nodes
+/// that do not need to survive a restart
+///
+/// Clones share the same contents, like clones of the RocksDB store do.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of stored keys
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of `notify_read` calls still waiting
+    pub(crate) fn waiting(&self) -> usize {
+        self.inner.lock().unwrap().waiting.values().map(Vec::len).sum()
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn write(
+        &mut self,
+        key: Key,
+        value: Value,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.prune();
+        if let Some(waiting) = inner.waiting.remove(&key) {
+            for tx_value in waiting {
+                let _ = tx_value.send(value.clone());
+            }
+        }
+        inner.values.insert(key, value);
+    }
+
+    async fn read(
+        &mut self,
+        key: Key,
+    ) -> StoreResult<Option<Value>> {
+        Ok(self.inner.lock().unwrap().values.get(&key).cloned())
+    }
+
+    async fn notify_read(
+        &mut self,
+        key: Key,
+    ) -> StoreResult<Value> {
+        let rx_value = {
+            let mut inner = self.inner.lock().unwrap();
+            if let Some(value) = inner.values.get(&key) {
+                return Ok(value.clone());
+            }
+            inner.prune();
+            let (tx_value, rx_value) = oneshot::channel();
+            inner.waiting.entry(key).or_default().push(tx_value);
+            rx_value
+        };
+        // The senders live in the store, which we hold, and are only pruned
+        // once we stopped waiting, so they are only ever dropped after sending
+        Ok(rx_value.await.expect("Pending read was dropped"))
+    }
+
+    async fn delete(
+        &mut self,
+        key: Key,
+    ) {
+        self.inner.lock().unwrap().values.remove(&key);
+    }
+}
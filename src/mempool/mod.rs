@@ -1,102 +1,174 @@
 use crate::{
-    Batch, BatchHash, Config, ConsensusMempoolMsg, Helper, MempoolHandler, MempoolMsg, Processor,
-    Synchronizer, Transaction, TxReceiveHandler,
+    serve_metrics, Batch, BatchHash, BatchStore, Config, ConsensusMempoolMsg, Helper,
+    MempoolHandler, MempoolMsg, MempoolNetwork, MempoolQuery, Metrics, Processed, Processor,
+    Reassembler, Scrubber, Synchronizer, SynchronizerQuery, Transaction, TxReceiveHandler,
 };
 use futures::StreamExt;
 use libcrypto::hash::Hash;
 use serde::{de::DeserializeOwned, Serialize};
 use std::fmt::Debug;
 use std::net::SocketAddr;
-use tcp_receiver::TcpReceiver;
 use tcp_sender::TcpSimpleSender;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    watch,
+};
 
-pub struct Mempool<Id, Round, Storage, Tx> {
+pub struct Mempool<Id, Round, Storage, Tx, Net = TcpSimpleSender<Id, MempoolMsg<Id, Tx>>> {
     /// The Id of this server
     my_name: Id,
     /// The Ids of all the servers
     all_ids: Vec<Id>,
-    /// The parameters for the mempool
+    /// The parameters for the mempool, as of the spawn
     params: Config<Round>,
+    /// The updates of the parameters
+    rx_config: watch::Receiver<Config<Round>>,
     /// The DB implementation to handle new transactions
-    store: Storage,
-    /// The networking object to send mempool messages to other mempools
-    mempool_sender: TcpSimpleSender<Id, MempoolMsg<Id, Tx>>,
+    store: BatchStore<Storage, Tx>,
+    /// The metrics of this mempool
+    metrics: Metrics,
+    /// The networking object to send mempool messages to other mempools, and
+    /// to listen to them and to the clients
+    mempool_sender: Net,
     /// Address where this mempool should listen to requests from other mempools
     mempool_addr: SocketAddr,
     /// Address where this mempool should listen to requests from clients
     client_addr: SocketAddr,
 }
 
-impl<Id, Round, Storage, Tx> Mempool<Id, Round, Storage, Tx>
+impl<Id, Round, Storage, Tx, Net> Mempool<Id, Round, Storage, Tx, Net>
 where
     Id: Debug + Clone + Eq + std::hash::Hash + Send + Sync + Serialize + DeserializeOwned + 'static,
-    Round: crate::Round,
+    Round: crate::Round + DeserializeOwned,
     Storage: libstorage::Store,
     Tx: Transaction,
+    Net: MempoolNetwork<Id>,
 {
+    /// Spawns every task of the mempool. Panics if the config does not validate,
+    /// which it always does if it comes from a `ConfigUpdater`.
     #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         my_name: Id,
         all_ids: Vec<Id>,
-        params: Config<Round>,
+        // The parameters for the mempool. Some of them can be updated at runtime,
+        // see `ConfigUpdater`.
+        rx_config: watch::Receiver<Config<Round>>,
+        metrics: Metrics,
         store: Storage,
-        mempool_sender: TcpSimpleSender<Id, MempoolMsg<Id, Tx>>,
+        mempool_sender: Net,
         rx_consensus: UnboundedReceiver<ConsensusMempoolMsg<Id, Round, Tx>>,
         // This channel is used to output the obtained transactions along with its size to whoever
         // is managing the batching process
         tx_batcher: UnboundedSender<(Tx, /* Size of the tx */ usize)>,
-        // This channel is used to output the batches obtained as responses to batching requests
-        // for database processing
+        // No longer used: the batches obtained as responses to batching requests go to the
+        // processor through a channel of their own, so that they are not taken for ours. Kept so
+        // that callers do not break.
         tx_processor: UnboundedSender<Batch<Tx>>,
-        // This is used to obtain batches that are ready to be processed
-        // E.g., the consensus will let us know once a batch is ready to be proposed
-        // We will typically forward this to the tx_processor
+        // This is used to obtain the batches sealed by our batcher, which are ready to be
+        // processed. Batches received from other mempools are processed separately, so that
+        // only our own are re-injected.
         rx_processor: UnboundedReceiver<Batch<Tx>>,
         // This channel is used to notify that a batch is processed and ready for consumption (by
         // consensus for e.g.).
         tx_consensus: UnboundedSender<BatchHash<Tx>>,
         mempool_addr: SocketAddr,
         client_addr: SocketAddr,
-    ) {
+    ) -> MempoolQuery<Storage, Tx> {
+        drop(tx_processor);
+        let params = rx_config.borrow().clone();
+        if let Err(e) = params.validate() {
+            panic!("Invalid mempool config: {}", e);
+        }
         // NOTE: This log entry is used to compute performance.
         params.log();
 
+        if let Some(addr) = params.metrics_addr {
+            let registry = metrics.registry().clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_metrics(registry, addr).await {
+                    log::error!("Failed to serve metrics on {}: {}", addr, e);
+                }
+            });
+        }
+
         let mut mempool = Self {
             my_name,
             all_ids,
             params,
-            store,
+            rx_config,
+            store: BatchStore::new(store),
+            metrics,
             mempool_sender,
             mempool_addr,
             client_addr,
         };
 
+        // The processor informs the synchronizer of every stored batch, so that
+        // it can be garbage collected later
+        let (tx_gc, rx_gc) = unbounded_channel();
+        // The batches received from other mempools are stored by the processor too
+        let (tx_synced, rx_synced) = unbounded_channel();
+        // Transactions of uncommitted batches are fed back to the batcher
+        let tx_reinject = tx_batcher.clone();
+        // Local queries (e.g., for the pending digests) are answered by the synchronizer
+        let (tx_query, rx_query) = unbounded_channel();
+        let (tx_report, rx_report) = watch::channel(None);
+        let query = MempoolQuery::new(mempool.store.clone(), tx_query.clone(), rx_report);
+        // Corrupted batches found in the store are fetched again by the synchronizer
+        let (tx_resync, rx_resync) = unbounded_channel();
+
+        if let Some(interval) = mempool.params.scrub_interval {
+            Scrubber::spawn(
+                mempool.store.clone(),
+                interval,
+                tx_resync.clone(),
+                tx_report,
+                mempool.metrics.clone(),
+            );
+        }
+
         mempool.handle_client_messages(
             tx_batcher,   // Output client tx [to batcher]
             rx_processor, // Input ready batches [from batcher] to the processor
+            rx_synced,    // Input batches [from other mempools] to the processor
             tx_consensus, // Output batch hash [to consensus]
+            tx_gc,        // Output batch hash [to synchronizer]
         );
 
-        mempool.handle_mempool_messages(tx_processor);
+        mempool.handle_mempool_messages(tx_synced, tx_query, tx_resync);
 
-        mempool.handle_consensus_messages(rx_consensus);
+        mempool.handle_consensus_messages(
+            rx_consensus,
+            rx_gc,
+            rx_query,
+            rx_resync,
+            tx_reinject,
+        );
+
+        query
     }
 
     /// Spawn all tasks responsible to handle messages from the consensus.
     fn handle_consensus_messages(
         self,
         rx_consensus: UnboundedReceiver<ConsensusMempoolMsg<Id, Round, Tx>>,
+        rx_gc: UnboundedReceiver<Processed<Tx>>,
+        rx_query: UnboundedReceiver<SynchronizerQuery<Tx>>,
+        rx_resync: UnboundedReceiver<BatchHash<Tx>>,
+        tx_reinject: UnboundedSender<(Tx, usize)>,
     ) {
         Synchronizer::spawn(
             self.my_name,
             rx_consensus,
-            self.params.gc_depth,
+            rx_gc,
+            rx_query,
+            rx_resync,
+            self.rx_config,
             self.mempool_sender,
             self.store.clone(),
-            self.params.sync_retry_delay,
             self.all_ids.clone(),
-            self.params.sync_retry_nodes,
+            tx_reinject,
+            self.metrics,
         );
     }
 
@@ -107,12 +179,14 @@ where
         tx_batcher: UnboundedSender<(Tx, usize)>,
         // Receive batches and process them
         rx_processor: UnboundedReceiver<Batch<Tx>>,
+        rx_synced: UnboundedReceiver<Batch<Tx>>,
         tx_consensus: UnboundedSender<Hash<Batch<Tx>>>,
+        tx_gc: UnboundedSender<Processed<Tx>>,
     ) {
         // Handle transactions sent by the client
-        // New API: TcpReceiver is a stream, poll it and forward to handler
-        let mut client_receiver = TcpReceiver::<Tx>::spawn(self.client_addr);
-        let tx_handler = TxReceiveHandler::new(tx_batcher);
+        // The receiver is a stream, poll it and forward to handler
+        let mut client_receiver = self.mempool_sender.receive::<Tx>(self.client_addr);
+        let tx_handler = TxReceiveHandler::new(tx_batcher, self.metrics.clone());
         tokio::spawn(async move {
             while let Some(result) = client_receiver.next().await {
                 match result {
@@ -130,25 +204,50 @@ where
         Processor::spawn(
             self.store.clone(),
             rx_processor, // From the batcher
+            rx_synced,    // From the other mempools
             tx_consensus, // Output to
+            tx_gc,
+            self.metrics.clone(),
         );
     }
 
     fn handle_mempool_messages(
         &mut self,
-        tx_processor: UnboundedSender<Batch<Tx>>,
+        tx_synced: UnboundedSender<Batch<Tx>>,
+        tx_query: UnboundedSender<SynchronizerQuery<Tx>>,
+        tx_resync: UnboundedSender<BatchHash<Tx>>,
     ) {
         let (tx_helper, rx_helper) = unbounded_channel();
+        let (tx_reassembler, rx_reassembler) = unbounded_channel();
 
-        Helper::<Id, Storage, Tx>::spawn(
-            TcpSimpleSender::with_peers(self.mempool_sender.get_peers().clone()),
+        Helper::<Id, Storage, Tx, Net>::spawn(
+            self.my_name.clone(),
+            self.mempool_sender.fork(),
             rx_helper,
             self.store.clone(),
+            self.params.sync_chunk_size,
+            self.params.sync_chunk_window,
+            self.params.sync_max_bytes_per_request,
+            self.params.sync_helper_workers,
+            tx_resync,
+            self.metrics.clone(),
+        );
+
+        // Large batches are streamed in chunks, put them back together
+        Reassembler::<Id, Tx, Net>::spawn(
+            self.my_name.clone(),
+            self.mempool_sender.fork(),
+            rx_reassembler,
+            tx_synced.clone(),
+            tx_query,
+            self.params.max_batch_size,
         );
 
-        // New API: TcpReceiver is a stream, poll it and forward to handler
-        let mut mempool_receiver = TcpReceiver::<MempoolMsg<Id, Tx>>::spawn(self.mempool_addr);
-        let mempool_handler = MempoolHandler::new(tx_helper, tx_processor);
+        // The receiver is a stream, poll it and forward to handler
+        let mut mempool_receiver = self
+            .mempool_sender
+            .receive::<MempoolMsg<Id, Tx>>(self.mempool_addr);
+        let mempool_handler = MempoolHandler::new(tx_helper, tx_reassembler, tx_synced);
         tokio::spawn(async move {
             while let Some(result) = mempool_receiver.next().await {
                 match result {
@@ -1,11 +1,12 @@
-use crate::{Batch, BatchHash, MempoolMsg, Transaction};
+use crate::{Batch, BatchChunk, HelperRequest, MempoolMsg, Transaction};
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use tokio::sync::mpsc::UnboundedSender;
 
 #[derive(Debug, Clone)]
 pub struct MempoolHandler<Id, Tx> {
-    tx_helper: UnboundedSender<(Id, Vec<BatchHash<Tx>>)>,
+    tx_helper: UnboundedSender<HelperRequest<Id, Tx>>,
+    tx_reassembler: UnboundedSender<(Id, BatchChunk<Tx>)>,
     tx_processor: UnboundedSender<Batch<Tx>>,
     _x: PhantomData<Id>,
 }
@@ -16,11 +17,13 @@ where
     Id: Debug + Clone + Send + Sync + 'static,
 {
     pub fn new(
-        tx_helper: UnboundedSender<(Id, Vec<BatchHash<Tx>>)>,
+        tx_helper: UnboundedSender<HelperRequest<Id, Tx>>,
+        tx_reassembler: UnboundedSender<(Id, BatchChunk<Tx>)>,
         tx_processor: UnboundedSender<Batch<Tx>>,
     ) -> Self {
         Self {
             tx_helper,
+            tx_reassembler,
             tx_processor,
             _x: PhantomData,
         }
@@ -33,7 +36,13 @@ where
                 let _ = self.tx_processor.send(batch);
             }
             MempoolMsg::RequestBatch(source, hashes) => {
-                let _ = self.tx_helper.send((source, hashes));
+                let _ = self.tx_helper.send(HelperRequest::Batches(source, hashes));
+            }
+            MempoolMsg::RequestBatchChunks(source, hash, offset) => {
+                let _ = self.tx_helper.send(HelperRequest::Chunks(source, hash, offset));
+            }
+            MempoolMsg::BatchChunk(source, chunk) => {
+                let _ = self.tx_reassembler.send((source, chunk));
             }
         }
     }
@@ -0,0 +1,233 @@
+use super::{Histogram, Registry, LATENCY_BUCKETS};
+use crate::{tx_hash, Batch, BatchHash};
+use fnv::FnvHashMap;
+use serde::Serialize;
+use std::fmt;
+use std::time::Duration;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use tokio::time::Instant;
+
+/// The maximum number of samples in flight at each stage. Once a stage is
+/// full, its samples older than `MAX_SAMPLE_AGE` are dropped, and new samples
+/// are ignored if that did not make room.
+const MAX_IN_FLIGHT: usize = 10_000;
+
+/// Samples that did not reach the next stage for this long are given up on,
+/// e.g., transactions of a batch that was lost
+const MAX_SAMPLE_AGE: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct InFlight {
+    /// When the sampled transactions were submitted, by digest
+    submitted: FnvHashMap<Vec<u8>, Instant>,
+    /// When the batches with a sampled transaction were sealed
+    sealed: FnvHashMap<Vec<u8>, Instant>,
+    /// When the batches with a sampled transaction were stored
+    stored: FnvHashMap<Vec<u8>, Instant>,
+}
+
+/// Starts following a sample at a stage, if there is room for it
+fn track(
+    stage: &mut FnvHashMap<Vec<u8>, Instant>,
+    key: Vec<u8>,
+    now: Instant,
+) {
+    if stage.len() >= MAX_IN_FLIGHT {
+        stage.retain(|_, since| now - *since < MAX_SAMPLE_AGE);
+    }
+    if stage.len() < MAX_IN_FLIGHT {
+        stage.insert(key, now);
+    }
+}
+
+struct Inner {
+    /// One transaction out of `sample_rate` is followed
+    sample_rate: u64,
+    received: AtomicU64,
+    in_flight: Mutex<InFlight>,
+    client_to_batch: Histogram,
+    batch_to_stored: Histogram,
+    stored_to_consensus: Histogram,
+}
+
+/// Follows a sample of the transactions from their submission until
+/// consensus commits them, and records how long each stage took
+///
+/// Disabled unless created with `LatencyTracker::new`.
+#[derive(Clone, Default)]
+pub struct LatencyTracker {
+    inner: Option<Arc<Inner>>,
+}
+
+impl LatencyTracker {
+    /// Follows one transaction out of `sample_rate`
+    pub fn new(
+        registry: &Registry,
+        sample_rate: u64,
+    ) -> Self {
+        Self {
+            inner: Some(Arc::new(Inner {
+                sample_rate: sample_rate.max(1),
+                received: AtomicU64::new(0),
+                in_flight: Mutex::new(InFlight::default()),
+                client_to_batch: registry.histogram(
+                    "mempool_client_to_batch_seconds",
+                    "Time from the submission of a transaction to the sealing of its batch",
+                    LATENCY_BUCKETS,
+                ),
+                batch_to_stored: registry.histogram(
+                    "mempool_batch_to_stored_seconds",
+                    "Time from the sealing of a batch to its storage",
+                    LATENCY_BUCKETS,
+                ),
+                stored_to_consensus: registry.histogram(
+                    "mempool_stored_to_consensus_seconds",
+                    "Time from the storage of a batch to its commit by consensus",
+                    LATENCY_BUCKETS,
+                ),
+            })),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// A transaction was submitted by a client
+    pub fn submitted<Tx>(
+        &self,
+        tx: &Tx,
+    ) where
+        Tx: Serialize,
+    {
+        let inner = match &self.inner {
+            Some(inner) => inner,
+            None => return,
+        };
+        if inner.received.fetch_add(1, Ordering::Relaxed) % inner.sample_rate != 0 {
+            return;
+        }
+        let mut in_flight = inner.in_flight.lock().unwrap();
+        track(&mut in_flight.submitted, tx_hash(tx).to_vec(), Instant::now());
+    }
+
+    /// A batch was sealed
+    pub fn sealed<Tx>(
+        &self,
+        batch: &Batch<Tx>,
+    ) where
+        Tx: Serialize,
+    {
+        let inner = match &self.inner {
+            Some(inner) => inner,
+            None => return,
+        };
+        let now = Instant::now();
+        if inner.in_flight.lock().unwrap().submitted.is_empty() {
+            return;
+        }
+        // Hashing is done outside the lock, which every stage contends for
+        let txs: Vec<_> = batch.payload.iter().map(|tx| tx_hash(tx).to_vec()).collect();
+        let mut sampled = false;
+        {
+            let mut in_flight = inner.in_flight.lock().unwrap();
+            for tx in &txs {
+                if let Some(submitted) = in_flight.submitted.remove(tx) {
+                    inner.client_to_batch.observe(now - submitted);
+                    sampled = true;
+                }
+            }
+        }
+        if sampled {
+            let digest = batch.digest().to_vec();
+            track(&mut inner.in_flight.lock().unwrap().sealed, digest, now);
+        }
+    }
+
+    /// A batch was written to the store
+    pub fn stored<Tx>(
+        &self,
+        digest: &BatchHash<Tx>,
+    ) {
+        let inner = match &self.inner {
+            Some(inner) => inner,
+            None => return,
+        };
+        let mut in_flight = inner.in_flight.lock().unwrap();
+        if let Some(sealed) = in_flight.sealed.remove(&digest.to_vec()) {
+            let now = Instant::now();
+            inner.batch_to_stored.observe(now - sealed);
+            track(&mut in_flight.stored, digest.to_vec(), now);
+        }
+    }
+
+    /// Consensus committed a batch
+    pub fn committed<Tx>(
+        &self,
+        digest: &BatchHash<Tx>,
+    ) {
+        let inner = match &self.inner {
+            Some(inner) => inner,
+            None => return,
+        };
+        let mut in_flight = inner.in_flight.lock().unwrap();
+        if let Some(stored) = in_flight.stored.remove(&digest.to_vec()) {
+            inner.stored_to_consensus.observe(stored.elapsed());
+        }
+    }
+
+    /// The average latency of every stage so far
+    pub fn report(&self) -> LatencyReport {
+        let stage = |histogram: &Histogram| {
+            histogram.mean().map(|mean| StageLatency {
+                mean,
+                samples: histogram.count(),
+            })
+        };
+        match &self.inner {
+            Some(inner) => LatencyReport {
+                client_to_batch: stage(&inner.client_to_batch),
+                batch_to_stored: stage(&inner.batch_to_stored),
+                stored_to_consensus: stage(&inner.stored_to_consensus),
+            },
+            None => LatencyReport::default(),
+        }
+    }
+}
+
+/// The average latency of a stage
+#[derive(Debug, Clone, Copy)]
+pub struct StageLatency {
+    pub mean: Duration,
+    pub samples: u64,
+}
+
+/// The average latency of every stage that has samples
+#[derive(Debug, Clone, Default)]
+pub struct LatencyReport {
+    pub client_to_batch: Option<StageLatency>,
+    pub batch_to_stored: Option<StageLatency>,
+    pub stored_to_consensus: Option<StageLatency>,
+}
+
+/// One line per stage, in the same format as the benchmarks of the tests, i.e.,
+/// `Bench {stage}: {latency} ms`
+impl fmt::Display for LatencyReport {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        let stages = [
+            ("client_to_batch", self.client_to_batch),
+            ("batch_to_stored", self.batch_to_stored),
+            ("stored_to_consensus", self.stored_to_consensus),
+        ];
+        for (name, stage) in stages.iter().filter_map(|(name, stage)| Some((name, (*stage)?))) {
+            writeln!(f, "Bench {}: {} ms", name, stage.mean.as_millis())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use crate::network::accept;
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+pub use latency::*;
+pub use registry::*;
+
+mod latency;
+mod registry;
+
+/// The metrics of the mempool
+///
+/// Clones update the same metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    /// Transactions handed to the batcher, including re-injected ones
+    pub transactions_received: Counter,
+    /// Transactions waiting in the batcher channel, as of the latest one the
+    /// batcher took
+    pub transactions_queued: Gauge,
+    /// Transactions fed back to the batcher because their batch was not
+    /// committed in time
+    pub transactions_reinjected: Counter,
+    /// Batches written to the store, ours or synchronized
+    pub batches_stored: Counter,
+    /// Bytes of the batches written to the store
+    pub bytes_stored: Counter,
+    /// Batches deleted by the garbage collector
+    pub batches_collected: Counter,
+    /// Batches in the store, that the synchronizer keeps track of
+    pub batches_tracked: Gauge,
+    /// Batch requests sent to other mempools, including retries
+    pub sync_requests_sent: Counter,
+    /// Batch requests sent again, after the first node did not answer
+    pub sync_retries: Counter,
+    /// Batches we gave up on
+    pub sync_failures: Counter,
+    /// Batches the synchronizer is waiting for
+    pub sync_pending: Gauge,
+    /// The retries of every batch, once synchronized or given up on
+    pub sync_attempts: Histogram,
+    /// Sync requests from other mempools that were served
+    pub sync_requests_served: Counter,
+    /// Sync requests from other mempools that were dropped
+    pub sync_requests_dropped: Counter,
+    /// Sync requests from other mempools waiting to be served
+    pub sync_requests_queued: Gauge,
+    /// Bytes sent in response to sync requests
+    pub sync_bytes_served: Counter,
+    /// Stored batches that did not match their digests
+    pub batches_corrupted: Counter,
+    /// The latency of a sample of the transactions, disabled by default
+    pub latency: LatencyTracker,
+}
+
+impl Metrics {
+    pub fn new(registry: &Registry) -> Self {
+        Self {
+            registry: registry.clone(),
+            transactions_received: registry.counter(
+                "mempool_transactions_received_total",
+                "Transactions handed to the batcher, including re-injected ones",
+            ),
+            transactions_queued: registry.gauge(
+                "mempool_transactions_queued",
+                "Transactions waiting in the batcher channel",
+            ),
+            transactions_reinjected: registry.counter(
+                "mempool_transactions_reinjected_total",
+                "Transactions of uncommitted batches fed back to the batcher",
+            ),
+            batches_stored: registry.counter(
+                "mempool_batches_stored_total",
+                "Batches written to the store",
+            ),
+            bytes_stored: registry.counter(
+                "mempool_stored_bytes_total",
+                "Bytes of the batches written to the store",
+            ),
+            batches_collected: registry.counter(
+                "mempool_batches_collected_total",
+                "Batches deleted by the garbage collector",
+            ),
+            batches_tracked: registry.gauge(
+                "mempool_batches_tracked",
+                "Stored batches tracked for garbage collection",
+            ),
+            sync_requests_sent: registry.counter(
+                "mempool_sync_requests_sent_total",
+                "Batch requests sent to other mempools",
+            ),
+            sync_retries: registry.counter(
+                "mempool_sync_retries_total",
+                "Batch requests sent again after a timeout",
+            ),
+            sync_failures: registry.counter(
+                "mempool_sync_failures_total",
+                "Batches that could not be synchronized",
+            ),
+            sync_pending: registry.gauge(
+                "mempool_sync_pending",
+                "Batches the synchronizer is waiting for",
+            ),
+            sync_attempts: registry.histogram(
+                "mempool_sync_attempts",
+                "Retries per batch, once synchronized or given up on",
+                ATTEMPT_BUCKETS,
+            ),
+            sync_requests_served: registry.counter(
+                "mempool_sync_requests_served_total",
+                "Sync requests from other mempools that were served",
+            ),
+            sync_requests_dropped: registry.counter(
+                "mempool_sync_requests_dropped_total",
+                "Sync requests from other mempools that were dropped",
+            ),
+            sync_requests_queued: registry.gauge(
+                "mempool_sync_requests_queued",
+                "Sync requests from other mempools waiting to be served",
+            ),
+            sync_bytes_served: registry.counter(
+                "mempool_sync_bytes_served_total",
+                "Bytes sent in response to sync requests",
+            ),
+            batches_corrupted: registry.counter(
+                "mempool_batches_corrupted_total",
+                "Stored batches that did not match their digests",
+            ),
+            latency: LatencyTracker::default(),
+        }
+    }
+
+    /// Measures how long one transaction out of `sample_rate` takes to get
+    /// sealed, stored, and committed
+    pub fn with_latency_sampling(
+        mut self,
+        sample_rate: u64,
+    ) -> Self {
+        self.latency = LatencyTracker::new(&self.registry, sample_rate);
+        self
+    }
+
+    /// The registry these metrics are registered in
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Batches sealed by the given sealer
+    pub fn batches_sealed(
+        &self,
+        sealer: &str,
+    ) -> Counter {
+        self.registry.counter_with_labels(
+            "mempool_batches_sealed_total",
+            "Batches sealed by the batcher",
+            &[("sealer", sealer)],
+        )
+    }
+
+    /// Transactions in the batches sealed by the given sealer
+    pub fn transactions_sealed(
+        &self,
+        sealer: &str,
+    ) -> Counter {
+        self.registry.counter_with_labels(
+            "mempool_transactions_sealed_total",
+            "Transactions in the batches sealed by the batcher",
+            &[("sealer", sealer)],
+        )
+    }
+}
+
+impl Default for Metrics {
+    /// Metrics in a registry of their own
+    fn default() -> Self {
+        Self::new(&Registry::new())
+    }
+}
+
+/// Serves the metrics of the registry over HTTP, in the Prometheus text
+/// format, whatever the request. `addr` must be a loopback address.
+pub async fn serve_metrics(
+    registry: Registry,
+    addr: SocketAddr,
+) -> anyhow::Result<()> {
+    if !addr.ip().is_loopback() {
+        return Err(anyhow::anyhow!(
+            "The metrics endpoint must listen on a loopback address, not {}",
+            addr
+        ));
+    }
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Serving metrics on {}", addr);
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, peer) = accept(&listener, "Metrics").await;
+            let registry = registry.clone();
+            tokio::spawn(async move {
+                // We do not care about the request, but read it so that the
+                // client does not see a reset connection
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let body = registry.render();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                if let Err(e) = socket.write_all(response.as_bytes()).await {
+                    log::debug!("Failed to send metrics to {}: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
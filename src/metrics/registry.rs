@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{
+    atomic::{AtomicI64, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+/// A value that only goes up
+#[derive(Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(
+        &self,
+        n: u64,
+    ) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that goes up and down
+#[derive(Clone, Default)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1);
+    }
+
+    pub fn add(
+        &self,
+        n: i64,
+    ) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn set(
+        &self,
+        n: i64,
+    ) {
+        self.0.store(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The upper bounds of the buckets of the latency histograms, in seconds
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The upper bounds of the buckets of the histograms of attempts
+pub const ATTEMPT_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 3.0, 5.0, 8.0, 13.0];
+
+struct HistogramInner {
+    /// The upper bounds of the buckets
+    bounds: Vec<f64>,
+    /// The number of observations in each bucket (not cumulative)
+    buckets: Vec<AtomicU64>,
+    /// The sum of the observations, in millionths
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+/// The distribution of a duration, or of a plain number
+#[derive(Clone)]
+pub struct Histogram(Arc<HistogramInner>);
+
+impl Histogram {
+    pub fn new(bounds: &[f64]) -> Self {
+        Self(Arc::new(HistogramInner {
+            bounds: bounds.to_vec(),
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn observe(
+        &self,
+        duration: Duration,
+    ) {
+        self.observe_value(duration.as_secs_f64());
+    }
+
+    /// Records a plain number, e.g., a number of attempts
+    pub fn observe_value(
+        &self,
+        value: f64,
+    ) {
+        if let Some(i) = self.0.bounds.iter().position(|bound| value <= *bound) {
+            self.0.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.0
+            .sum_micros
+            .fetch_add((value * 1_000_000.0) as u64, Ordering::Relaxed);
+        self.0.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The number of observations
+    pub fn count(&self) -> u64 {
+        self.0.count.load(Ordering::Relaxed)
+    }
+
+    /// The sum of the observations
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.0.sum_micros.load(Ordering::Relaxed))
+    }
+
+    /// The average of the observations, if any
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_micros(
+                self.0.sum_micros.load(Ordering::Relaxed) / count,
+            )),
+        }
+    }
+
+    /// The (cumulative) number of observations below each bound
+    fn cumulative(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        self.0
+            .bounds
+            .iter()
+            .zip(&self.0.buckets)
+            .map(|(bound, bucket)| {
+                total += bucket.load(Ordering::Relaxed);
+                (*bound, total)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+            MetricKind::Histogram => "histogram",
+        }
+    }
+}
+
+/// The value of a metric (with the given labels) at some point in time
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// The name of the metric this sample belongs to. It differs from `name`
+    /// for the samples of a histogram (e.g., `*_bucket`).
+    pub family: String,
+    pub name: String,
+    pub help: String,
+    pub kind: MetricKind,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+/// A source of samples that is not registered metric by metric, e.g., the
+/// metrics of another library
+pub trait Collector: Send + Sync + 'static {
+    fn collect(&self) -> Vec<Sample>;
+}
+
+#[derive(Clone)]
+enum Value {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+impl Value {
+    /// The samples of this metric, as (name suffix, extra label, value)
+    fn samples(&self) -> Vec<(&'static str, Option<(String, String)>, f64)> {
+        match self {
+            Value::Counter(counter) => vec![("", None, counter.get() as f64)],
+            Value::Gauge(gauge) => vec![("", None, gauge.get() as f64)],
+            Value::Histogram(histogram) => {
+                let mut samples: Vec<_> = histogram
+                    .cumulative()
+                    .into_iter()
+                    .map(|(bound, count)| {
+                        ("_bucket", Some(("le".to_string(), bound.to_string())), count as f64)
+                    })
+                    .collect();
+                let count = histogram.count() as f64;
+                samples.push(("_bucket", Some(("le".to_string(), "+Inf".to_string())), count));
+                samples.push(("_sum", None, histogram.sum().as_secs_f64()));
+                samples.push(("_count", None, count));
+                samples
+            }
+        }
+    }
+}
+
+struct Family {
+    help: String,
+    kind: MetricKind,
+    /// The metrics of this family, by labels
+    series: BTreeMap<Vec<(String, String)>, Value>,
+}
+
+#[derive(Default)]
+struct Inner {
+    families: BTreeMap<String, Family>,
+    collectors: Vec<Arc<dyn Collector>>,
+}
+
+/// The metrics of a node. Registering a metric twice (with the same labels)
+/// returns the same metric, so that several components can share a registry.
+#[derive(Clone, Default)]
+pub struct Registry {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counter(
+        &self,
+        name: &str,
+        help: &str,
+    ) -> Counter {
+        self.counter_with_labels(name, help, &[])
+    }
+
+    pub fn counter_with_labels(
+        &self,
+        name: &str,
+        help: &str,
+        labels: &[(&str, &str)],
+    ) -> Counter {
+        let value = self.register(name, help, MetricKind::Counter, labels, || {
+            Value::Counter(Counter::default())
+        });
+        match value {
+            Value::Counter(counter) => counter,
+            _ => panic!("Metric {} is already registered with another type", name),
+        }
+    }
+
+    pub fn gauge(
+        &self,
+        name: &str,
+        help: &str,
+    ) -> Gauge {
+        let value = self.register(name, help, MetricKind::Gauge, &[], || {
+            Value::Gauge(Gauge::default())
+        });
+        match value {
+            Value::Gauge(gauge) => gauge,
+            _ => panic!("Metric {} is already registered with another type", name),
+        }
+    }
+
+    /// A histogram of durations, with the given bucket bounds (in seconds), or
+    /// of plain numbers
+    pub fn histogram(
+        &self,
+        name: &str,
+        help: &str,
+        bounds: &[f64],
+    ) -> Histogram {
+        let value = self.register(name, help, MetricKind::Histogram, &[], || {
+            Value::Histogram(Histogram::new(bounds))
+        });
+        match value {
+            Value::Histogram(histogram) => histogram,
+            _ => panic!("Metric {} is already registered with another type", name),
+        }
+    }
+
+    /// Adds samples from elsewhere to the ones of this registry
+    pub fn register_collector(
+        &self,
+        collector: Arc<dyn Collector>,
+    ) {
+        self.inner.lock().unwrap().collectors.push(collector);
+    }
+
+    fn register(
+        &self,
+        name: &str,
+        help: &str,
+        kind: MetricKind,
+        labels: &[(&str, &str)],
+        new: impl FnOnce() -> Value,
+    ) -> Value {
+        let mut inner = self.inner.lock().unwrap();
+        let family = inner.families.entry(name.to_string()).or_insert_with(|| Family {
+            help: help.to_string(),
+            kind,
+            series: BTreeMap::new(),
+        });
+        let labels = labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        family.series.entry(labels).or_insert_with(new).clone()
+    }
+
+    /// Returns the current value of every metric
+    pub fn gather(&self) -> Vec<Sample> {
+        let inner = self.inner.lock().unwrap();
+        let mut samples = Vec::new();
+        for (name, family) in &inner.families {
+            for (labels, value) in &family.series {
+                for (suffix, extra, value) in value.samples() {
+                    let mut labels = labels.clone();
+                    labels.extend(extra);
+                    samples.push(Sample {
+                        family: name.clone(),
+                        name: format!("{}{}", name, suffix),
+                        help: family.help.clone(),
+                        kind: family.kind,
+                        labels,
+                        value,
+                    });
+                }
+            }
+        }
+        for collector in &inner.collectors {
+            samples.extend(collector.collect());
+        }
+        samples
+    }
+
+    /// Returns the current value of every metric, in the Prometheus text
+    /// format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut last = None;
+        for sample in self.gather() {
+            if last.as_ref() != Some(&sample.family) {
+                let _ = writeln!(out, "# HELP {} {}", sample.family, sample.help);
+                let _ = writeln!(out, "# TYPE {} {}", sample.family, sample.kind.as_str());
+                last = Some(sample.family.clone());
+            }
+            out.push_str(&sample.name);
+            if !sample.labels.is_empty() {
+                let labels: Vec<_> = sample
+                    .labels
+                    .iter()
+                    .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                    .collect();
+                let _ = write!(out, "{{{}}}", labels.join(","));
+            }
+            let _ = writeln!(out, " {}", sample.value);
+        }
+        out
+    }
+}
+
+/// Escapes a label value, as the Prometheus text format requires
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
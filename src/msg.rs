@@ -1,27 +1,246 @@
 use std::fmt::{Debug, Formatter, self};
 
+use bytes::{BufMut, Bytes, BytesMut};
 use libcrypto::hash::Hash;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{
+    de::{self, DeserializeOwned, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::marker::PhantomData;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
 
 /// A short-hand to represent Hash<Batch<Tx>>
 pub type BatchHash<Tx> = Hash<Batch<Tx>>;
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A short-hand to represent Hash<Tx>
+pub type TxHash<Tx> = Hash<Tx>;
+
+/// Computes the digest of a transaction
+pub fn tx_hash<Tx>(tx: &Tx) -> TxHash<Tx>
+where
+    Tx: Serialize,
+{
+    let serialized = bincode::serialize(tx).expect("Failed to serialize transaction");
+    Hash::do_hash(&serialized)
+}
+
+/// Who sealed a batch, and when
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct BatchHeader {
+    /// The (serialized) `Id` of the node that sealed the batch
+    pub author: Vec<u8>,
+    /// The worker of the author that sealed the batch
+    pub worker: u32,
+    /// The (serialized) consensus round in which the batch was sealed
+    pub round: Vec<u8>,
+    /// When the batch was sealed, in milliseconds since the UNIX epoch
+    pub timestamp: u64,
+}
+
+impl BatchHeader {
+    pub fn new<Id, Round>(
+        author: &Id,
+        worker: u32,
+        round: &Round,
+    ) -> Self
+    where
+        Id: Serialize,
+        Round: Serialize,
+    {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        Self {
+            author: bincode::serialize(author).expect("Failed to serialize author"),
+            worker,
+            round: bincode::serialize(round).expect("Failed to serialize round"),
+            timestamp,
+        }
+    }
+
+    /// The node that sealed the batch
+    pub fn author<Id>(&self) -> anyhow::Result<Id>
+    where
+        Id: DeserializeOwned,
+    {
+        Ok(bincode::deserialize(&self.author)?)
+    }
+
+    /// The consensus round in which the batch was sealed
+    pub fn round<Round>(&self) -> anyhow::Result<Round>
+    where
+        Round: DeserializeOwned,
+    {
+        Ok(bincode::deserialize(&self.round)?)
+    }
+}
+
+/// Takes the place of the payload length in batches that have a header
+const HEADER_MARKER: u64 = u64::MAX;
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Batch<Tx> {
     pub payload: Vec<Tx>,
+    /// `None` for batches sealed before headers were introduced
+    pub header: Option<BatchHeader>,
 }
 
 impl<Tx> Debug for Batch<Tx> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("Batch")
+            .field("header", &self.header)
             .field("payload length", &self.payload.len())
             .finish()
     }
 }
 
+impl<Tx> Batch<Tx>
+where
+    Tx: Serialize,
+{
+    /// Computes the digest of the batch, as the processor does when storing it
+    pub fn digest(&self) -> BatchHash<Tx> {
+        let serialized = bincode::serialize(self).expect("Failed to serialize batch");
+        Hash::do_hash(&serialized)
+    }
+}
+
 impl<Tx> From<Vec<Tx>> for Batch<Tx> {
     fn from(tx_batch: Vec<Tx>) -> Self {
-        Self { payload: tx_batch }
+        Self {
+            payload: tx_batch,
+            header: None,
+        }
+    }
+}
+
+// A batch without a header is serialized exactly like the payload-only batches
+// that came before, so that their digests (and the stored batches) remain
+// valid. Batches with a header start with `HEADER_MARKER` instead of the
+// payload length.
+impl<Tx> Serialize for Batch<Tx>
+where
+    Tx: Serialize,
+{
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match &self.header {
+            None => {
+                let mut tuple = serializer.serialize_tuple(1 + self.payload.len())?;
+                tuple.serialize_element(&(self.payload.len() as u64))?;
+                for tx in &self.payload {
+                    tuple.serialize_element(tx)?;
+                }
+                tuple.end()
+            }
+            Some(header) => {
+                let mut tuple = serializer.serialize_tuple(3)?;
+                tuple.serialize_element(&HEADER_MARKER)?;
+                tuple.serialize_element(header)?;
+                tuple.serialize_element(&self.payload)?;
+                tuple.end()
+            }
+        }
+    }
+}
+
+impl<'de, Tx> Deserialize<'de> for Batch<Tx>
+where
+    Tx: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct BatchVisitor<Tx>(PhantomData<Tx>);
+
+        impl<'de, Tx> Visitor<'de> for BatchVisitor<Tx>
+        where
+            Tx: Deserialize<'de>,
+        {
+            type Value = Batch<Tx>;
+
+            fn expecting(
+                &self,
+                f: &mut Formatter<'_>,
+            ) -> fmt::Result {
+                f.write_str("a batch")
+            }
+
+            fn visit_seq<A>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let len: u64 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                if len == HEADER_MARKER {
+                    let header = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                    let payload = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                    return Ok(Batch {
+                        payload,
+                        header: Some(header),
+                    });
+                }
+
+                // Do not trust the length for the allocation
+                let mut payload = Vec::with_capacity(len.min(4096) as usize);
+                for i in 0..len {
+                    let tx = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(1 + i as usize, &self))?;
+                    payload.push(tx);
+                }
+                Ok(Batch {
+                    payload,
+                    header: None,
+                })
+            }
+        }
+
+        deserializer.deserialize_tuple(usize::MAX, BatchVisitor(PhantomData))
+    }
+}
+
+/// A part of a serialized batch, used to stream large batches to other
+/// mempools
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchChunk<Tx> {
+    /// The digest of the whole batch
+    pub digest: BatchHash<Tx>,
+    /// The position of this chunk in the serialized batch
+    pub offset: u64,
+    /// The size of the serialized batch
+    pub total: u64,
+    /// Whether the helper waits for us to ask for more after this chunk
+    pub window_end: bool,
+    pub data: Vec<u8>,
+}
+
+impl<Tx> Debug for BatchChunk<Tx> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchChunk")
+            .field("digest", &self.digest)
+            .field("offset", &self.offset)
+            .field("total", &self.total)
+            .field("window_end", &self.window_end)
+            .field("data length", &self.data.len())
+            .finish()
     }
 }
 
@@ -30,6 +249,37 @@ pub enum MempoolMsg<Id, Tx> {
     RequestBatch(Id, Vec<BatchHash<Tx>>),
     /// This is sent by the primary or by a helper
     Batch(Batch<Tx>),
+    /// Ask for the rest of a streamed batch, starting at the given offset
+    RequestBatchChunks(Id, BatchHash<Tx>, /* offset */ u64),
+    /// This is sent by a helper, for batches too large to be sent at once
+    BatchChunk(Id, BatchChunk<Tx>),
+}
+
+impl<Id, Tx> MempoolMsg<Id, Tx>
+where
+    Id: Serialize,
+    Tx: Serialize,
+{
+    /// Frames a serialized batch, as written in the store, into a serialized
+    /// `MempoolMsg::Batch`, without deserializing it
+    pub fn frame_batch(serialized_batch: &[u8]) -> Bytes {
+        let tag = Self::batch_tag();
+        let mut buf = BytesMut::with_capacity(tag.len() + serialized_batch.len());
+        buf.put_slice(&tag);
+        buf.put_slice(serialized_batch);
+        buf.freeze()
+    }
+
+    /// The bytes that bincode writes before the batch of a `MempoolMsg::Batch`,
+    /// taken from an empty one rather than hard-coded
+    fn batch_tag() -> Vec<u8> {
+        let empty = Batch::<Tx>::from(Vec::new());
+        let batch = bincode::serialize(&empty).expect("Failed to serialize batch");
+        let mut message =
+            bincode::serialize(&Self::Batch(empty)).expect("Failed to serialize message");
+        message.truncate(message.len() - batch.len());
+        message
+    }
 }
 
 impl<Id, Tx> net_common::Message for MempoolMsg<Id, Tx>
@@ -46,5 +296,22 @@ where
 
 pub enum ConsensusMempoolMsg<Id, Round, Tx> {
     End(Round),
-    UnknownBatch(Id, Vec<BatchHash<Tx>>),
+    /// Synchronize these batches from the given node. If it does not answer,
+    /// we retry with the nodes that likely hold them (e.g., the ones that
+    /// certified or referenced them) first. The channel resolves once all of
+    /// them are in the store, or fails if they were garbage collected or could
+    /// not be synchronized.
+    UnknownBatch(
+        Id,
+        Vec<BatchHash<Tx>>,
+        /* Likely holders */ Vec<Id>,
+        oneshot::Sender<anyhow::Result<()>>,
+    ),
+    /// Keep these batches in the store, regardless of the gc round
+    Pin(Vec<BatchHash<Tx>>),
+    /// Release batches that were previously pinned, so that they can be
+    /// garbage collected
+    Release(Vec<BatchHash<Tx>>),
+    /// These batches were committed in this round and are now final
+    Committed(Round, Vec<BatchHash<Tx>>),
 }
@@ -0,0 +1,135 @@
+use crate::Transaction;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt};
+use serde::Serialize;
+use std::{fmt::Debug, net::SocketAddr, time::Duration};
+use tcp_receiver::TcpReceiver;
+use tcp_sender::TcpSimpleSender;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::sleep,
+};
+
+/// The pause after a failed `accept`, doubled on every failure in a row
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// The longest pause after a failed `accept`
+const ACCEPT_RETRY_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Accepts the next connection on the listener of `service`. Errors (e.g.,
+/// running out of file descriptors) tend to persist, so we back off instead
+/// of retrying right away.
+pub(crate) async fn accept(
+    listener: &TcpListener,
+    service: &str,
+) -> (TcpStream, SocketAddr) {
+    let mut delay = ACCEPT_RETRY_DELAY;
+    loop {
+        match listener.accept().await {
+            Ok(conn) => return conn,
+            Err(e) => {
+                log::warn!("{} listener error: {}", service, e);
+                sleep(delay).await;
+                delay = (delay * 2).min(ACCEPT_RETRY_MAX_DELAY);
+            }
+        }
+    }
+}
+
+/// How a mempool talks to the other mempools, and listens to them and to its
+/// clients
+///
+/// The messages are serialized by the caller. Sends are best effort: a message
+/// may be lost even if the send succeeds.
+#[async_trait]
+pub trait MempoolNetwork<Id>: Send + 'static
+where
+    Id: Clone + Send + Sync + 'static,
+{
+    /// Another handle to the same peers, e.g., for another task
+    fn fork(&self) -> Self;
+
+    /// Every node we can send to
+    fn peers(&self) -> Vec<Id>;
+
+    async fn send(
+        &mut self,
+        recipient: Id,
+        data: Bytes,
+    ) -> Result<()>;
+
+    /// Sends `data` to every node in `recipients`. Fails if any of the sends
+    /// failed, after trying all of them.
+    async fn multicast(
+        &mut self,
+        recipients: &[Id],
+        data: Bytes,
+    ) -> Result<()> {
+        let mut result = Ok(());
+        for recipient in recipients {
+            if let Err(e) = self.send(recipient.clone(), data.clone()).await {
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Sends `data` to every peer
+    async fn broadcast(
+        &mut self,
+        data: Bytes,
+    ) -> Result<()> {
+        let peers = self.peers();
+        self.multicast(&peers, data).await
+    }
+
+    /// The messages received on `addr`, e.g., the transactions of the clients
+    /// or the `MempoolMsg`s of the other mempools
+    fn receive<T>(
+        &self,
+        addr: SocketAddr,
+    ) -> BoxStream<'static, Result<T>>
+    where
+        T: Transaction;
+}
+
+/// The default transport, over the TCP connections of libnet-rs
+#[async_trait]
+impl<Id, M> MempoolNetwork<Id> for TcpSimpleSender<Id, M>
+where
+    Id: Debug + Clone + Eq + std::hash::Hash + Send + Sync + Serialize + 'static,
+    M: Transaction,
+{
+    /// Opens its own connections to the peers
+    fn fork(&self) -> Self {
+        TcpSimpleSender::with_peers(self.get_peers().clone())
+    }
+
+    fn peers(&self) -> Vec<Id> {
+        self.get_peers().keys().cloned().collect()
+    }
+
+    async fn send(
+        &mut self,
+        recipient: Id,
+        data: Bytes,
+    ) -> Result<()> {
+        TcpSimpleSender::send(self, recipient, data)
+            .await
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    fn receive<T>(
+        &self,
+        addr: SocketAddr,
+    ) -> BoxStream<'static, Result<T>>
+    where
+        T: Transaction,
+    {
+        TcpReceiver::<T>::spawn(addr)
+            .map(|result| result.map_err(|e| anyhow!("{:?}", e)))
+            .boxed()
+    }
+}
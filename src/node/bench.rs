@@ -0,0 +1,85 @@
+use super::RawTx;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The first byte of the sample transactions of `mempool-bench`
+const SAMPLE_TAG: u8 = 0;
+/// The first byte of the other transactions of `mempool-bench`
+const STANDARD_TAG: u8 = 1;
+/// The tag, followed by the id of the transaction
+pub const MIN_TX_SIZE: usize = 1 + 8;
+
+/// The prefix of the node-side marker of a sample transaction
+const MARKER: &str = "Sample transaction ";
+
+/// Transactions that may be samples of `mempool-bench`, followed through the
+/// node with markers in the logs
+pub trait Sampled {
+    /// The id of the sample, if this transaction is one
+    fn sample_id(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl Sampled for RawTx {
+    fn sample_id(&self) -> Option<u64> {
+        match self.0.split_first() {
+            Some((&SAMPLE_TAG, rest)) if rest.len() >= 8 => {
+                Some(u64::from_be_bytes(rest[..8].try_into().unwrap()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl RawTx {
+    /// The sample transaction `id`, padded to `size` bytes
+    pub fn sample(
+        id: u64,
+        size: usize,
+    ) -> Self {
+        Self::tagged(SAMPLE_TAG, id, size)
+    }
+
+    /// A transaction that is not a sample, unique for every `counter`
+    pub fn standard(
+        counter: u64,
+        size: usize,
+    ) -> Self {
+        Self::tagged(STANDARD_TAG, counter, size)
+    }
+
+    fn tagged(
+        tag: u8,
+        id: u64,
+        size: usize,
+    ) -> Self {
+        let mut data = Vec::with_capacity(size.max(MIN_TX_SIZE));
+        data.push(tag);
+        data.extend_from_slice(&id.to_be_bytes());
+        data.resize(size.max(MIN_TX_SIZE), 0);
+        Self(data)
+    }
+}
+
+/// Milliseconds since the Unix epoch, which the markers of the nodes and of
+/// the client are compared with
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// The log line of a node once the batch with sample `id` is stored, e.g.
+/// `Sample transaction 3 ready at 1700000000000`
+pub fn sample_marker(id: u64) -> String {
+    format!("{}{} ready at {}", MARKER, id, unix_millis())
+}
+
+/// Finds the id of the sample and the time it was ready, in a log line
+pub fn parse_sample_marker(line: &str) -> Option<(u64, u64)> {
+    let (_, marker) = line.split_once(MARKER)?;
+    let (id, at) = marker.split_once(" ready at ")?;
+    let at = at.split_whitespace().next()?;
+    Some((id.parse().ok()?, at.parse().ok()?))
+}
@@ -0,0 +1,177 @@
+use crate::{network::accept, BatchHash, ConsensusMempoolMsg, Transaction};
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot, watch,
+    },
+};
+
+/// The requests of consensus to the node, one JSON object per line, e.g.
+/// `{"cmd": "end", "round": 7}`
+///
+/// Digests are in the JSON form of `BatchHash`, as sent in `Batch` events.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ConsensusRequest<Id, Round, Tx> {
+    /// The round ended
+    End { round: Round },
+    /// Synchronize these batches from `source`. A `Synced` event follows once
+    /// they all arrive, or fail to.
+    UnknownBatch {
+        source: Id,
+        digests: Vec<BatchHash<Tx>>,
+        /// The nodes that likely hold the batches
+        #[serde(default = "Vec::new")]
+        holders: Vec<Id>,
+    },
+    /// These batches were committed in this round
+    Committed {
+        round: Round,
+        digests: Vec<BatchHash<Tx>>,
+    },
+    /// Keep these batches, regardless of the gc round
+    Pin { digests: Vec<BatchHash<Tx>> },
+    /// Release batches that were previously pinned
+    Release { digests: Vec<BatchHash<Tx>> },
+}
+
+/// The events sent by the node to consensus, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ConsensusEvent<Tx> {
+    /// A batch is stored, and can be proposed
+    Batch { digest: BatchHash<Tx> },
+    /// The batches of an `unknown_batch` request are all in the store, unless
+    /// there is an error
+    Synced {
+        digests: Vec<BatchHash<Tx>>,
+        error: Option<String>,
+    },
+    /// A request was not understood
+    Error { error: String },
+}
+
+/// Lets consensus drive the mempool over a loopback socket, with the
+/// `ConsensusRequest` and `ConsensusEvent` line protocol
+///
+/// One consensus is served at a time. The batches stored while it is not
+/// connected are sent once it connects.
+pub async fn serve_consensus<Id, Round, Tx>(
+    addr: SocketAddr,
+    mut rx_batches: UnboundedReceiver<BatchHash<Tx>>,
+    tx_consensus: UnboundedSender<ConsensusMempoolMsg<Id, Round, Tx>>,
+    tx_round: watch::Sender<Round>,
+) -> Result<()>
+where
+    Id: DeserializeOwned + Send + 'static,
+    Round: crate::Round + DeserializeOwned,
+    Tx: Transaction,
+{
+    if !addr.ip().is_loopback() {
+        return Err(anyhow!(
+            "The consensus interface must listen on a loopback address, not {}",
+            addr
+        ));
+    }
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Serving consensus on {}", addr);
+    tokio::spawn(async move {
+        loop {
+            let (socket, peer) = accept(&listener, "Consensus").await;
+            log::info!("Consensus connected from {}", peer);
+            let served = serve_connection(socket, &mut rx_batches, &tx_consensus, &tx_round);
+            if let Err(e) = served.await {
+                log::warn!("Consensus connection with {} failed: {}", peer, e);
+            }
+            log::info!("Consensus at {} disconnected", peer);
+        }
+    });
+    Ok(())
+}
+
+async fn serve_connection<Id, Round, Tx>(
+    socket: TcpStream,
+    rx_batches: &mut UnboundedReceiver<BatchHash<Tx>>,
+    tx_consensus: &UnboundedSender<ConsensusMempoolMsg<Id, Round, Tx>>,
+    tx_round: &watch::Sender<Round>,
+) -> Result<()>
+where
+    Id: DeserializeOwned + Send + 'static,
+    Round: crate::Round + DeserializeOwned,
+    Tx: Transaction,
+{
+    let (reader, mut writer) = socket.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    // The outcome of the sync requests, as they resolve
+    let (tx_event, mut rx_event) = unbounded_channel();
+    loop {
+        let event = tokio::select! {
+            line = lines.next_line() => match line? {
+                Some(line) => match handle_request(&line, tx_consensus, tx_round, &tx_event) {
+                    Ok(()) => continue,
+                    Err(e) => ConsensusEvent::Error { error: e.to_string() },
+                },
+                None => return Ok(()),
+            },
+            Some(digest) = rx_batches.recv() => ConsensusEvent::Batch { digest },
+            Some(event) = rx_event.recv() => event,
+        };
+        let mut line = serde_json::to_string(&event)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+    }
+}
+
+fn handle_request<Id, Round, Tx>(
+    line: &str,
+    tx_consensus: &UnboundedSender<ConsensusMempoolMsg<Id, Round, Tx>>,
+    tx_round: &watch::Sender<Round>,
+    tx_event: &UnboundedSender<ConsensusEvent<Tx>>,
+) -> Result<()>
+where
+    Id: DeserializeOwned,
+    Round: crate::Round + DeserializeOwned,
+    Tx: Transaction,
+{
+    let request = serde_json::from_str(line).map_err(|e| anyhow!("Invalid request: {}", e))?;
+    let message = match request {
+        ConsensusRequest::End { round } => {
+            tx_round.send_replace(round);
+            ConsensusMempoolMsg::End(round)
+        }
+        ConsensusRequest::UnknownBatch {
+            source,
+            digests,
+            holders,
+        } => {
+            let (tx_done, rx_done) = oneshot::channel();
+            let tx_event = tx_event.clone();
+            let requested = digests.clone();
+            tokio::spawn(async move {
+                let error = match rx_done.await {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(e.to_string()),
+                    Err(_) => Some("Mempool is shutting down".to_string()),
+                };
+                let _ = tx_event.send(ConsensusEvent::Synced {
+                    digests: requested,
+                    error,
+                });
+            });
+            ConsensusMempoolMsg::UnknownBatch(source, digests, holders, tx_done)
+        }
+        ConsensusRequest::Committed { round, digests } => {
+            ConsensusMempoolMsg::Committed(round, digests)
+        }
+        ConsensusRequest::Pin { digests } => ConsensusMempoolMsg::Pin(digests),
+        ConsensusRequest::Release { digests } => ConsensusMempoolMsg::Release(digests),
+    };
+    tx_consensus
+        .send(message)
+        .map_err(|_| anyhow!("Mempool is shutting down"))
+}
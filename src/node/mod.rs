@@ -0,0 +1,215 @@
+use crate::{
+    batcher::{Batcher, BatcherHandle},
+    sealer::{HybridSealer, Sized, Timed},
+    serve_admin, Admin, BatchHash, Committee, ConfigUpdater, Mempool, MempoolMsg, MempoolQuery,
+    Metrics, NodeConfig, SealerKind, Transaction,
+};
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fmt::Debug, time::Duration};
+use tcp_sender::TcpSimpleSender;
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver},
+        watch,
+    },
+    time,
+};
+
+pub use bench::*;
+pub use consensus::*;
+
+mod bench;
+mod consensus;
+
+/// How often the latencies of the sampled transactions are logged
+const LATENCY_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The transactions of a standalone node, opaque to the mempool
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawTx(pub Vec<u8>);
+
+impl net_common::Message for RawTx {
+    type DeserializationError = Box<bincode::ErrorKind>;
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Self::DeserializationError> {
+        bincode::deserialize(bytes)
+    }
+}
+
+/// A mempool running on its own: the mempool, a batcher, and the local
+/// interfaces for consensus and operators
+pub struct MempoolNode<Round, Storage, Tx> {
+    /// Queries the batches and transactions of the node
+    pub query: MempoolQuery<Storage, Tx>,
+    /// Drives the batcher
+    pub batcher: BatcherHandle,
+    /// Updates the config of the node at runtime
+    pub config: ConfigUpdater<Round>,
+    pub metrics: Metrics,
+}
+
+impl<Round, Storage, Tx> MempoolNode<Round, Storage, Tx>
+where
+    Round: crate::Round + Serialize + DeserializeOwned,
+    Storage: libstorage::Store,
+    Tx: Transaction + Sampled,
+{
+    /// Spawns every component of the node described by `config`
+    pub async fn spawn<Id>(
+        config: NodeConfig<Id, Round>,
+        committee: Committee<Id>,
+        store: Storage,
+    ) -> Result<Self>
+    where
+        Id: Debug
+            + Clone
+            + Eq
+            + std::hash::Hash
+            + Send
+            + Sync
+            + Serialize
+            + DeserializeOwned
+            + 'static,
+    {
+        config.validate(&committee)?;
+        let mempool_addr = config.mempool_addr(&committee)?;
+        let client_addr = config.client_addr(&committee)?;
+        let updater = ConfigUpdater::new(config.mempool.clone())?;
+        let metrics = match config.latency_sampling {
+            Some(rate) => Metrics::default().with_latency_sampling(rate),
+            None => Metrics::default(),
+        };
+
+        let (tx_batcher, rx_batcher) = unbounded_channel();
+        let (tx_processor, rx_processor) = unbounded_channel();
+        let (tx_consensus, rx_consensus) = unbounded_channel();
+        let (tx_batches, mut rx_batches) = unbounded_channel();
+        let (tx_round, rx_round) = watch::channel(Round::MIN);
+
+        // Batches are stamped with the latest round that consensus ended
+        let params = config.mempool.sealer;
+        let name = config.name.clone();
+        let worker = config.worker;
+        let output = tx_processor.clone();
+        let batcher = match config.sealer {
+            SealerKind::Sized => Batcher::spawn_with_header(
+                rx_batcher,
+                output,
+                Sized::new(params.size),
+                name,
+                worker,
+                rx_round,
+                metrics.clone(),
+            ),
+            SealerKind::Timed => Batcher::spawn_with_header(
+                rx_batcher,
+                output,
+                Timed::new(params.timeout),
+                name,
+                worker,
+                rx_round,
+                metrics.clone(),
+            ),
+            SealerKind::Hybrid => Batcher::spawn_with_header(
+                rx_batcher,
+                output,
+                HybridSealer::new(params.timeout, params.size),
+                name,
+                worker,
+                rx_round,
+                metrics.clone(),
+            ),
+        };
+        batcher.follow(updater.subscribe());
+
+        let query = Mempool::spawn(
+            config.name.clone(),
+            committee.ids(),
+            updater.subscribe(),
+            metrics.clone(),
+            store,
+            TcpSimpleSender::<Id, MempoolMsg<Id, Tx>>::with_peers(committee.mempool_peers()),
+            rx_consensus,
+            tx_batcher,
+            tx_processor,
+            rx_processor,
+            tx_batches,
+            mempool_addr,
+            client_addr,
+        );
+
+        if let Some(addr) = config.admin_addr {
+            let admin = Admin::new(query.clone(), Some(batcher.clone()), updater.subscribe());
+            serve_admin(admin, addr).await?;
+        }
+
+        if config.benchmark {
+            rx_batches = mark_samples(rx_batches, query.clone());
+        }
+
+        match config.consensus_addr {
+            Some(addr) => serve_consensus(addr, rx_batches, tx_consensus, tx_round).await?,
+            None => {
+                tokio::spawn(async move {
+                    while let Some(digest) = rx_batches.recv().await {
+                        log::info!("Batch {} is ready", digest);
+                    }
+                });
+            }
+        }
+
+        if metrics.latency.is_enabled() {
+            let latency = metrics.latency.clone();
+            tokio::spawn(async move {
+                let mut interval = time::interval(LATENCY_REPORT_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    let report = latency.report().to_string();
+                    if !report.is_empty() {
+                        // NOTE: These log entries are used to compute performance.
+                        log::info!("{}", report.trim_end());
+                    }
+                }
+            });
+        }
+
+        Ok(Self {
+            query,
+            batcher,
+            config: updater,
+            metrics,
+        })
+    }
+}
+
+/// Logs the marker of the sample transactions of every batch that is ready,
+/// before passing the batch on
+fn mark_samples<Storage, Tx>(
+    mut rx_batches: UnboundedReceiver<BatchHash<Tx>>,
+    mut query: MempoolQuery<Storage, Tx>,
+) -> UnboundedReceiver<BatchHash<Tx>>
+where
+    Storage: libstorage::Store,
+    Tx: Transaction + Sampled,
+{
+    let (tx_marked, rx_marked) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(digest) = rx_batches.recv().await {
+            match query.batch(&digest).await {
+                Ok(Some(batch)) => {
+                    for id in batch.payload.iter().filter_map(Sampled::sample_id) {
+                        // NOTE: These log entries are used to compute performance.
+                        log::info!("{}", sample_marker(id));
+                    }
+                }
+                Ok(None) => log::warn!("Batch {} is ready but not in the store", digest),
+                Err(e) => log::warn!("Failed to read batch {}: {}", digest, e),
+            }
+            if tx_marked.send(digest).is_err() {
+                break;
+            }
+        }
+    });
+    rx_marked
+}
@@ -0,0 +1,295 @@
+use crate::Config;
+use anyhow::{anyhow, Context, Result};
+use fnv::{FnvHashMap, FnvHashSet};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fmt::Debug,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+/// The prefix of the environment variables that override the node config
+pub const ENV_PREFIX: &str = "MEMPOOL_";
+
+/// Separates the fields of nested sections in the name of an environment
+/// variable, e.g. `MEMPOOL_MEMPOOL__GC_DEPTH`
+const ENV_SEPARATOR: &str = "__";
+
+/// The addresses of a node of the committee
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member<Id> {
+    pub id: Id,
+    /// Where the node listens to other mempools
+    pub mempool_addr: SocketAddr,
+    /// Where the node listens to clients
+    pub client_addr: SocketAddr,
+}
+
+/// The nodes running the mempool
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Committee<Id> {
+    pub members: Vec<Member<Id>>,
+}
+
+impl<Id> Committee<Id>
+where
+    Id: Debug + Clone + Eq + std::hash::Hash + Serialize + DeserializeOwned,
+{
+    /// Reads the committee from a TOML or JSON file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        read_file(path.as_ref())
+    }
+
+    /// The number of nodes
+    pub fn size(&self) -> usize {
+        self.members.len()
+    }
+
+    /// The ids of all the nodes
+    pub fn ids(&self) -> Vec<Id> {
+        self.members.iter().map(|member| member.id.clone()).collect()
+    }
+
+    pub fn member(
+        &self,
+        id: &Id,
+    ) -> Option<&Member<Id>> {
+        self.members.iter().find(|member| &member.id == id)
+    }
+
+    /// The mempool address of every node
+    pub fn mempool_peers(&self) -> FnvHashMap<Id, SocketAddr> {
+        self.members
+            .iter()
+            .map(|member| (member.id.clone(), member.mempool_addr))
+            .collect()
+    }
+
+    /// The client address of every node
+    pub fn client_peers(&self) -> FnvHashMap<Id, SocketAddr> {
+        self.members
+            .iter()
+            .map(|member| (member.id.clone(), member.client_addr))
+            .collect()
+    }
+
+    /// Checks that the committee is not empty, and that no id or address is
+    /// used twice
+    pub fn validate(&self) -> Result<()> {
+        if self.members.is_empty() {
+            return Err(anyhow!("The committee is empty"));
+        }
+        let mut ids = FnvHashSet::default();
+        let mut addrs = FnvHashSet::default();
+        for member in &self.members {
+            if !ids.insert(&member.id) {
+                return Err(anyhow!("Node {:?} appears twice in the committee", member.id));
+            }
+            for addr in [member.mempool_addr, member.client_addr] {
+                if !addrs.insert(addr) {
+                    return Err(anyhow!("Address {} is used twice in the committee", addr));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The sealer used by the batcher of a node
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SealerKind {
+    /// Seals once the batch reaches `sealer.size`
+    Sized,
+    /// Seals every `sealer.timeout`
+    Timed,
+    /// Seals on whichever comes first
+    #[default]
+    Hybrid,
+}
+
+/// The configuration of a mempool node
+///
+/// Every field but `name` is optional in the files, and any of them can be
+/// overridden with an environment variable named after it, e.g.
+/// `MEMPOOL_ADMIN_ADDR=127.0.0.1:7000` or `MEMPOOL_MEMPOOL__GC_DEPTH=50`.
+/// Values are read as JSON, falling back to strings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "Id: Serialize, Round: Serialize",
+    deserialize = "Id: Deserialize<'de>, Round: crate::Round + Deserialize<'de>"
+))]
+pub struct NodeConfig<Id, Round> {
+    /// The id of this node in the committee
+    pub name: Id,
+    /// The worker of this node that seals the batches, as stamped in their
+    /// headers. Defaults to 0.
+    #[serde(default)]
+    pub worker: u32,
+    /// Where to listen to other mempools. Defaults to our address in the
+    /// committee.
+    #[serde(default)]
+    pub mempool_addr: Option<SocketAddr>,
+    /// Where to listen to clients. Defaults to our address in the committee.
+    #[serde(default)]
+    pub client_addr: Option<SocketAddr>,
+    /// Where the admin endpoint is served. It must be a loopback address.
+    /// `None` (the default) disables it.
+    #[serde(default)]
+    pub admin_addr: Option<SocketAddr>,
+    /// Where consensus talks to the node, see `serve_consensus`. It must be a
+    /// loopback address. `None` (the default) disables it, and the batches
+    /// are only logged.
+    #[serde(default)]
+    pub consensus_addr: Option<SocketAddr>,
+    /// Follow one transaction out of this many through the pipeline, and log
+    /// how long each stage took. `None` (the default) disables this.
+    #[serde(default)]
+    pub latency_sampling: Option<u64>,
+    /// Log a marker once the batch of every sample transaction of
+    /// `mempool-bench` is stored. Defaults to `false`.
+    #[serde(default)]
+    pub benchmark: bool,
+    /// Where the batches are stored. Defaults to `db`.
+    #[serde(default = "default_store_path")]
+    pub store_path: PathBuf,
+    /// The sealer of the batcher. Defaults to `hybrid`.
+    #[serde(default)]
+    pub sealer: SealerKind,
+    /// The parameters of the mempool, see `Config`
+    #[serde(default)]
+    pub mempool: Config<Round>,
+}
+
+fn default_store_path() -> PathBuf {
+    PathBuf::from("db")
+}
+
+impl<Id, Round> NodeConfig<Id, Round>
+where
+    Id: Debug + Clone + Eq + std::hash::Hash + Serialize + DeserializeOwned,
+    Round: crate::Round + Serialize + DeserializeOwned,
+{
+    /// Reads the config from a TOML or JSON file, and applies the overrides
+    /// of the environment
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        read_file::<Self>(path.as_ref())?.override_with(std::env::vars(), ENV_PREFIX)
+    }
+
+    /// Applies the overrides found in `vars` whose name starts with `prefix`
+    pub fn override_with(
+        self,
+        vars: impl IntoIterator<Item = (String, String)>,
+        prefix: &str,
+    ) -> Result<Self> {
+        let mut config = serde_json::to_value(&self)?;
+        for (name, raw) in vars {
+            let key = match name.strip_prefix(prefix) {
+                Some(key) => key.to_lowercase(),
+                None => continue,
+            };
+            let path: Vec<&str> = key.split(ENV_SEPARATOR).collect();
+            let slot = match lookup(&mut config, &path) {
+                Some(slot) => slot,
+                None => {
+                    log::warn!("Ignoring {}, which is not a setting", name);
+                    continue;
+                }
+            };
+            *slot = serde_json::from_str(&raw).unwrap_or(Value::String(raw));
+            log::info!("Config: {} overridden by {}", path.join("."), name);
+        }
+        serde_json::from_value(config).context("Invalid config override")
+    }
+
+    /// Where to listen to other mempools
+    pub fn mempool_addr(
+        &self,
+        committee: &Committee<Id>,
+    ) -> Result<SocketAddr> {
+        self.mempool_addr
+            .or_else(|| committee.member(&self.name).map(|member| member.mempool_addr))
+            .ok_or_else(|| anyhow!("Node {:?} is not in the committee", self.name))
+    }
+
+    /// Where to listen to clients
+    pub fn client_addr(
+        &self,
+        committee: &Committee<Id>,
+    ) -> Result<SocketAddr> {
+        self.client_addr
+            .or_else(|| committee.member(&self.name).map(|member| member.client_addr))
+            .ok_or_else(|| anyhow!("Node {:?} is not in the committee", self.name))
+    }
+
+    /// Checks that the config makes sense for this committee
+    pub fn validate(
+        &self,
+        committee: &Committee<Id>,
+    ) -> Result<()> {
+        committee.validate()?;
+        self.mempool.validate()?;
+        if committee.member(&self.name).is_none() {
+            return Err(anyhow!("Node {:?} is not in the committee", self.name));
+        }
+        let others = committee.size() - 1;
+        if others > 0 && self.mempool.sync_retry_nodes > others {
+            return Err(anyhow!(
+                "sync_retry_nodes ({}) is larger than the number of other nodes ({})",
+                self.mempool.sync_retry_nodes,
+                others
+            ));
+        }
+        let mempool_addr = self.mempool_addr(committee)?;
+        let client_addr = self.client_addr(committee)?;
+        if mempool_addr == client_addr {
+            return Err(anyhow!("The mempool and client addresses are both {}", mempool_addr));
+        }
+        let mut in_use = vec![mempool_addr, client_addr];
+        let local = [
+            ("admin", self.admin_addr),
+            ("consensus", self.consensus_addr),
+            ("metrics", self.mempool.metrics_addr),
+        ];
+        for (name, addr) in local {
+            if let Some(addr) = addr {
+                if !addr.ip().is_loopback() {
+                    return Err(anyhow!("The {} address {} is not a loopback address", name, addr));
+                }
+                if in_use.contains(&addr) {
+                    return Err(anyhow!("The {} address {} is already in use", name, addr));
+                }
+                in_use.push(addr);
+            }
+        }
+        if self.latency_sampling == Some(0) {
+            return Err(anyhow!("latency_sampling must be positive, or disabled"));
+        }
+        Ok(())
+    }
+}
+
+/// Reads a TOML or JSON file, depending on its extension
+fn read_file<T>(path: &Path) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&data).map_err(anyhow::Error::from),
+        Some("json") => serde_json::from_str(&data).map_err(anyhow::Error::from),
+        _ => return Err(anyhow!("{} is neither a .toml nor a .json file", path.display())),
+    };
+    parsed.with_context(|| format!("Invalid config in {}", path.display()))
+}
+
+/// Finds the field at `path` in a config
+fn lookup<'a>(
+    config: &'a mut Value,
+    path: &[&str],
+) -> Option<&'a mut Value> {
+    path.iter()
+        .try_fold(config, |value, field| value.as_object_mut()?.get_mut(*field))
+}
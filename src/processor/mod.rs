@@ -1,8 +1,18 @@
-use crate::{Batch, BatchHash, Transaction};
-use libcrypto::hash::Hash;
+use crate::{tx_hash, Batch, BatchHash, BatchHeader, BatchStore, Metrics, Transaction};
+use tracing::{field, Instrument};
 use std::marker::PhantomData;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
+/// A batch that the processor wrote to the store
+#[derive(Debug)]
+pub struct Processed<Tx> {
+    pub digest: BatchHash<Tx>,
+    /// Whether we sealed the batch, rather than received it from another node
+    pub own: bool,
+    /// The header of the batch, if it has one
+    pub header: Option<BatchHeader>,
+}
+
 /// This data structure will take batches and add them to the database and
 /// forward the hash for consumption signalling that the batch is ready for use
 pub struct Processor<Storage, Tx> {
@@ -15,20 +25,51 @@ where
     Tx: Transaction,
 {
     pub fn spawn(
-        mut store: Storage,
-        // Input channel to receive batches.
+        mut store: BatchStore<Storage, Tx>,
+        // Input channel to receive the batches we sealed.
         mut rx_processor: UnboundedReceiver<Batch<Tx>>,
+        // Input channel to receive the batches of other nodes.
+        mut rx_synced: UnboundedReceiver<Batch<Tx>>,
         // Output channel to send out batches' digests.
         tx_hash: UnboundedSender<BatchHash<Tx>>,
+        // Output channel to let the synchronizer track stored batches for gc.
+        tx_gc: UnboundedSender<Processed<Tx>>,
+        metrics: Metrics,
     ) {
         tokio::spawn(async move {
-            while let Some(batch) = rx_processor.recv().await {
-                let serialized_batch =
-                    bincode::serialize(&batch).expect("Failed to serialize batch");
-                // Hash the batch
-                let hash: BatchHash<Tx> = Hash::do_hash(&serialized_batch);
-                store.write(hash.to_vec(), serialized_batch).await;
+            loop {
+                let (batch, own) = tokio::select! {
+                    Some(batch) = rx_processor.recv() => (batch, true),
+                    Some(batch) = rx_synced.recv() => (batch, false),
+                    else => break,
+                };
+                let span = tracing::debug_span!(
+                    "process_batch",
+                    batch = field::Empty,
+                    txs = batch.payload.len()
+                );
+                // Store the batch, and index its transactions by their digests
+                let hash = store.put(&batch).instrument(span.clone()).await;
+                span.record("batch", field::display(&hash));
+                span.in_scope(|| {
+                    if tracing::enabled!(tracing::Level::TRACE) {
+                        for tx in &batch.payload {
+                            tracing::trace!(tx = %tx_hash(tx), "Stored transaction");
+                        }
+                    }
+                    tracing::debug!("Stored batch");
+                });
+                metrics.latency.stored(&hash);
+                metrics.batches_stored.inc();
+                if let Ok(size) = bincode::serialized_size(&batch) {
+                    metrics.bytes_stored.inc_by(size);
+                }
 
+                let _ = tx_gc.send(Processed {
+                    digest: hash.clone(),
+                    own,
+                    header: batch.header.clone(),
+                });
                 let _ = tx_hash.send(hash);
             }
         });
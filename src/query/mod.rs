@@ -0,0 +1,134 @@
+use crate::{
+    tx_hash, Batch, BatchHash, BatchHeader, BatchStore, PeerHealth, ScrubReport, SyncStatus,
+    SynchronizerQuery, Transaction, TxHash,
+};
+use anyhow::{anyhow, Result};
+use tokio::sync::{mpsc::UnboundedSender, oneshot, watch};
+
+/// A local handle to query the batches and transactions held by the mempool
+pub struct MempoolQuery<Storage, Tx> {
+    store: BatchStore<Storage, Tx>,
+    tx_query: UnboundedSender<SynchronizerQuery<Tx>>,
+    rx_report: watch::Receiver<Option<ScrubReport<Tx>>>,
+}
+
+impl<Storage, Tx> Clone for MempoolQuery<Storage, Tx>
+where
+    Storage: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            tx_query: self.tx_query.clone(),
+            rx_report: self.rx_report.clone(),
+        }
+    }
+}
+
+impl<Storage, Tx> MempoolQuery<Storage, Tx>
+where
+    Storage: libstorage::Store,
+    Tx: Transaction,
+{
+    pub fn new(
+        store: BatchStore<Storage, Tx>,
+        tx_query: UnboundedSender<SynchronizerQuery<Tx>>,
+        rx_report: watch::Receiver<Option<ScrubReport<Tx>>>,
+    ) -> Self {
+        Self {
+            store,
+            tx_query,
+            rx_report,
+        }
+    }
+
+    /// Fetches a batch by its digest
+    pub async fn batch(
+        &mut self,
+        hash: &BatchHash<Tx>,
+    ) -> Result<Option<Batch<Tx>>> {
+        self.store.get(hash).await
+    }
+
+    /// Fetches the header of a batch, if the batch is in the store and has one
+    pub async fn header(
+        &mut self,
+        hash: &BatchHash<Tx>,
+    ) -> Result<Option<BatchHeader>> {
+        Ok(self.batch(hash).await?.and_then(|batch| batch.header))
+    }
+
+    /// Checks whether a batch is in the store, without deserializing it
+    pub async fn contains(
+        &mut self,
+        hash: &BatchHash<Tx>,
+    ) -> Result<bool> {
+        self.store.exists(hash).await
+    }
+
+    /// Returns the digests of the batches that the synchronizer is still
+    /// waiting for
+    pub async fn pending(&self) -> Result<Vec<BatchHash<Tx>>> {
+        self.ask(SynchronizerQuery::Pending).await
+    }
+
+    /// Returns a summary of the state of the synchronizer
+    pub async fn status(&self) -> Result<SyncStatus> {
+        self.ask(SynchronizerQuery::Status).await
+    }
+
+    /// Returns what the synchronizer observed about every peer it asked for
+    /// batches
+    pub async fn peers(&self) -> Result<Vec<PeerHealth>> {
+        self.ask(SynchronizerQuery::Peers).await
+    }
+
+    /// Deletes the batches older than the gc round right away, instead of
+    /// waiting for the end of the next round. Returns the number of deleted
+    /// batches.
+    pub async fn gc(&self) -> Result<usize> {
+        self.ask(SynchronizerQuery::Gc).await
+    }
+
+    /// Stops waiting for a batch, failing the consensus requests that need
+    /// it. Returns false if the synchronizer was not waiting for it.
+    pub async fn drop_sync(
+        &self,
+        hash: &BatchHash<Tx>,
+    ) -> Result<bool> {
+        self.ask(|tx_reply| SynchronizerQuery::Drop(hash.clone(), tx_reply))
+            .await
+    }
+
+    async fn ask<T>(
+        &self,
+        query: impl FnOnce(oneshot::Sender<T>) -> SynchronizerQuery<Tx>,
+    ) -> Result<T> {
+        let (tx_reply, rx_reply) = oneshot::channel();
+        self.tx_query
+            .send(query(tx_reply))
+            .map_err(|_| anyhow!("Synchronizer is shutting down"))?;
+        Ok(rx_reply.await?)
+    }
+
+    /// Returns the report of the latest pass of the scrubber, if any
+    pub fn scrub_report(&self) -> Option<ScrubReport<Tx>> {
+        self.rx_report.borrow().clone()
+    }
+
+    /// Fetches a transaction by its digest
+    pub async fn transaction(
+        &mut self,
+        digest: &TxHash<Tx>,
+    ) -> Result<Option<Tx>> {
+        let batch_hash = match self.store.batch_of(digest).await? {
+            Some(batch_hash) => batch_hash,
+            None => return Ok(None),
+        };
+        let tx = self
+            .batch(&batch_hash)
+            .await?
+            .and_then(|batch| batch.payload.into_iter().find(|tx| &tx_hash(tx) == digest));
+        Ok(tx)
+    }
+}
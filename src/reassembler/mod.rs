@@ -0,0 +1,209 @@
+use crate::{
+    Batch, BatchChunk, BatchHash, MempoolMsg, MempoolNetwork, SynchronizerQuery, Transaction,
+};
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use libcrypto::hash::Hash;
+use serde::Serialize;
+use std::fmt::Debug;
+use std::time::Duration;
+use tcp_sender::TcpSimpleSender;
+use tokio::{
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    time::Instant,
+};
+
+/// Streams that we did not hear of for this long are dropped
+pub(crate) const STREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The number of streams that a peer may have open with us at once
+const MAX_STREAMS_PER_SOURCE: usize = 4;
+
+/// A batch being reassembled
+struct Stream<Id> {
+    /// The peer that opened the stream
+    source: Id,
+    /// The size of the serialized batch
+    total: u64,
+    buf: Vec<u8>,
+    /// The last time we got a chunk
+    last_seen: Instant,
+}
+
+/// The Reassembler puts together the batches streamed in chunks by the helpers
+/// of other mempools, and forwards them to the processor once verified
+///
+/// Streams are only opened for batches that the synchronizer waits for, up to
+/// `max_batch_size` bytes, and `MAX_STREAMS_PER_SOURCE` at a time per peer.
+pub struct Reassembler<Id, Tx, Net = TcpSimpleSender<Id, MempoolMsg<Id, Tx>>>
+where
+    Tx: Transaction,
+{
+    my_name: Id,
+    mempool_sender: Net,
+    rx_chunk: UnboundedReceiver<(Id, BatchChunk<Tx>)>,
+    tx_processor: UnboundedSender<Batch<Tx>>,
+    /// Used to check that we are waiting for the batches streamed to us
+    tx_query: UnboundedSender<SynchronizerQuery<Tx>>,
+    max_batch_size: usize,
+    /// The batches being reassembled
+    streams: FnvHashMap<BatchHash<Tx>, Stream<Id>>,
+}
+
+impl<Id, Tx, Net> Reassembler<Id, Tx, Net>
+where
+    Id: Debug + Clone + Eq + std::hash::Hash + Send + Sync + Serialize + 'static,
+    Tx: Transaction,
+    Net: MempoolNetwork<Id>,
+{
+    pub fn spawn(
+        my_name: Id,
+        mempool_sender: Net,
+        rx_chunk: UnboundedReceiver<(Id, BatchChunk<Tx>)>,
+        tx_processor: UnboundedSender<Batch<Tx>>,
+        tx_query: UnboundedSender<SynchronizerQuery<Tx>>,
+        max_batch_size: usize,
+    ) {
+        tokio::spawn(async move {
+            Self {
+                my_name,
+                mempool_sender,
+                rx_chunk,
+                tx_processor,
+                tx_query,
+                max_batch_size,
+                streams: FnvHashMap::default(),
+            }
+            .run()
+            .await
+        });
+    }
+
+    async fn run(&mut self) {
+        while let Some((source, chunk)) = self.rx_chunk.recv().await {
+            self.streams
+                .retain(|_, stream| stream.last_seen.elapsed() < STREAM_TIMEOUT);
+
+            if !self.streams.contains_key(&chunk.digest) {
+                // Streams start at offset 0, the rest belongs to a stream that
+                // we already completed or dropped
+                if chunk.offset != 0 {
+                    log::debug!("Ignoring chunk of unknown stream {}", chunk.digest);
+                    continue;
+                }
+                if !self.accept(&source, &chunk).await {
+                    continue;
+                }
+                let stream = Stream {
+                    source: source.clone(),
+                    total: chunk.total,
+                    buf: Vec::new(),
+                    last_seen: Instant::now(),
+                };
+                self.streams.insert(chunk.digest.clone(), stream);
+            }
+
+            let stream = self.streams.get_mut(&chunk.digest).unwrap();
+            stream.last_seen = Instant::now();
+
+            // Chunks arrive in order, anything else is a duplicate (e.g., from
+            // another helper) or follows a lost chunk. Chunks that do not fit
+            // in the announced size are bogus.
+            if chunk.offset == stream.buf.len() as u64
+                && chunk.total == stream.total
+                && (stream.buf.len() + chunk.data.len()) as u64 <= stream.total
+            {
+                stream.buf.extend_from_slice(&chunk.data);
+            }
+            let received = stream.buf.len() as u64;
+
+            if received >= stream.total {
+                let stream = self.streams.remove(&chunk.digest).unwrap();
+                self.complete(chunk.digest, stream.buf);
+            } else if chunk.window_end {
+                self.request_more(source, chunk.digest, received).await;
+            }
+        }
+        log::warn!("Reassembler is shutting down");
+    }
+
+    /// Whether to open a stream for the batch of `chunk`: only if we are
+    /// waiting for it, if it is not too large, and if `source` has room for
+    /// another stream
+    async fn accept(
+        &self,
+        source: &Id,
+        chunk: &BatchChunk<Tx>,
+    ) -> bool {
+        if chunk.total > self.max_batch_size as u64 {
+            log::warn!(
+                "Ignoring stream of batch {} from {:?}: {} B exceeds the max batch size",
+                chunk.digest,
+                source,
+                chunk.total
+            );
+            return false;
+        }
+        let open = self
+            .streams
+            .values()
+            .filter(|stream| &stream.source == source)
+            .count();
+        if open >= MAX_STREAMS_PER_SOURCE {
+            log::debug!(
+                "Ignoring stream of batch {}: too many streams from {:?}",
+                chunk.digest,
+                source
+            );
+            return false;
+        }
+        let (tx_reply, rx_reply) = oneshot::channel();
+        let query = SynchronizerQuery::IsPending(chunk.digest.clone(), tx_reply);
+        if self.tx_query.send(query).is_err() {
+            return false;
+        }
+        let pending = rx_reply.await.unwrap_or(false);
+        if !pending {
+            log::debug!("Ignoring stream of batch {} that we did not ask for", chunk.digest);
+        }
+        pending
+    }
+
+    /// Verifies a reassembled batch and forwards it to the processor
+    fn complete(
+        &mut self,
+        digest: BatchHash<Tx>,
+        data: Vec<u8>,
+    ) {
+        let hash: BatchHash<Tx> = Hash::do_hash(&data);
+        if hash != digest {
+            log::warn!("Reassembled batch does not match its digest {}", digest);
+            return;
+        }
+        match bincode::deserialize::<Batch<Tx>>(&data) {
+            Ok(batch) => {
+                if let Err(e) = self.tx_processor.send(batch) {
+                    log::error!("Reassembler error: {}", e);
+                }
+            }
+            Err(e) => log::warn!("Failed to deserialize reassembled batch {}: {}", digest, e),
+        }
+    }
+
+    /// Asks the helper for the next window of chunks
+    async fn request_more(
+        &mut self,
+        source: Id,
+        digest: BatchHash<Tx>,
+        offset: u64,
+    ) {
+        let message = MempoolMsg::<Id, Tx>::RequestBatchChunks(self.my_name.clone(), digest, offset);
+        let serialized = Bytes::from(bincode::serialize(&message).unwrap());
+        if let Err(e) = self.mempool_sender.send(source, serialized).await {
+            log::warn!("Reassembler send error: {}", e);
+        }
+    }
+}
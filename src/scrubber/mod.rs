@@ -0,0 +1,121 @@
+use crate::{BatchHash, BatchStore, Metrics, Transaction};
+use std::marker::PhantomData;
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc::UnboundedSender, watch},
+    time::{sleep, Instant},
+};
+
+/// Moves a corrupted batch out of the way, so that it is neither served nor
+/// mistaken for the real one, and asks the synchronizer to fetch it again
+pub(crate) async fn quarantine<Storage, Tx>(
+    store: &mut BatchStore<Storage, Tx>,
+    digest: &BatchHash<Tx>,
+    data: Vec<u8>,
+    tx_resync: &UnboundedSender<BatchHash<Tx>>,
+) where
+    Storage: libstorage::Store,
+    Tx: Transaction,
+{
+    store.quarantine(digest, data).await;
+    let _ = tx_resync.send(digest.clone());
+}
+
+/// The outcome of a pass of the scrubber over the store
+#[derive(Debug, Clone)]
+pub struct ScrubReport<Tx> {
+    /// The number of batches checked
+    pub scanned: usize,
+    /// The number of batches that were garbage collected during the pass
+    pub missing: usize,
+    /// The batches that did not match their digest, and were quarantined
+    pub corrupted: Vec<BatchHash<Tx>>,
+    /// How long the pass took
+    pub duration: Duration,
+}
+
+/// The Scrubber periodically checks that the stored batches still hash to
+/// their digests. Corrupted batches are quarantined and synchronized again
+/// from other nodes.
+///
+/// The batches are found through the index of the store, which outlives the
+/// synchronizer across restarts.
+pub struct Scrubber<Storage, Tx> {
+    _x: PhantomData<(Storage, Tx)>,
+}
+
+impl<Storage, Tx> Scrubber<Storage, Tx>
+where
+    Storage: libstorage::Store,
+    Tx: Transaction,
+{
+    pub fn spawn(
+        mut store: BatchStore<Storage, Tx>,
+        interval: Duration,
+        // Used to fetch corrupted batches again
+        tx_resync: UnboundedSender<BatchHash<Tx>>,
+        // Used to publish the report of the latest pass
+        tx_report: watch::Sender<Option<ScrubReport<Tx>>>,
+        metrics: Metrics,
+    ) {
+        tokio::spawn(async move {
+            loop {
+                sleep(interval).await;
+
+                let digests = match store.scan().await {
+                    Ok(digests) => digests,
+                    Err(e) => {
+                        log::warn!("Failed to scan the store: {}", e);
+                        continue;
+                    }
+                };
+
+                let report = Self::scrub(&mut store, digests, &tx_resync, &metrics).await;
+                log::info!(
+                    "Scrubbed {} batches in {} ms: {} corrupted, {} missing",
+                    report.scanned,
+                    report.duration.as_millis(),
+                    report.corrupted.len(),
+                    report.missing
+                );
+                tx_report.send_replace(Some(report));
+            }
+            log::warn!("Scrubber is shutting down");
+        });
+    }
+
+    async fn scrub(
+        store: &mut BatchStore<Storage, Tx>,
+        digests: Vec<BatchHash<Tx>>,
+        tx_resync: &UnboundedSender<BatchHash<Tx>>,
+        metrics: &Metrics,
+    ) -> ScrubReport<Tx> {
+        let start = Instant::now();
+        let mut report = ScrubReport {
+            scanned: 0,
+            missing: 0,
+            corrupted: Vec::new(),
+            duration: Duration::default(),
+        };
+
+        for digest in digests {
+            match store.get_raw(&digest).await {
+                Ok(Some(data)) => {
+                    report.scanned += 1;
+                    let hash = BatchStore::<Storage, Tx>::digest(&data);
+                    if hash != digest {
+                        log::error!("Stored batch {} is corrupted, quarantining it", digest);
+                        quarantine(store, &digest, data, tx_resync).await;
+                        metrics.batches_corrupted.inc();
+                        report.corrupted.push(digest);
+                    }
+                }
+                Ok(None) => report.missing += 1,
+                Err(e) => log::warn!("Store Error: {}", e),
+            }
+        }
+
+        report.duration = start.elapsed();
+        report
+    }
+}
@@ -5,7 +5,7 @@ use std::{
 };
 
 use super::{Sized, Timed};
-use crate::{Sealer, Transaction};
+use crate::{Sealer, SealerParams, Transaction};
 use fnv::FnvHashMap as HashMap;
 use futures::{Future, FutureExt};
 
@@ -46,8 +46,17 @@ impl<Tx> Sealer<Tx> for HybridSealer<Tx>
 where
     Tx: Transaction,
 {
+    fn name(&self) -> &'static str {
+        "hybrid"
+    }
+
+    /// Returns all the transactions, in the order they arrived, and resets
+    /// the sealer
     fn seal(&mut self) -> Vec<Tx> {
-        panic!("Do not call .seal() for Hybrid Sealer\nUse .reset() and .await");
+        let mut txs: Vec<_> = std::mem::take(&mut self.map).into_iter().collect();
+        txs.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        self.reset();
+        txs.into_iter().map(|(_, tx)| tx).collect()
     }
 
     fn update(
@@ -60,6 +69,14 @@ where
         self.timed_sealer.update(self.counter, tx_size);
         self.counter += 1;
     }
+
+    fn reconfigure(
+        &mut self,
+        params: &SealerParams,
+    ) {
+        self.timed_sealer.reconfigure(params);
+        self.sized_sealer.reconfigure(params);
+    }
 }
 
 impl<Tx> Unpin for HybridSealer<Tx> where Tx: Transaction {}
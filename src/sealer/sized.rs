@@ -1,4 +1,4 @@
-use crate::Sealer;
+use crate::{Sealer, SealerParams};
 use std::{
     future::Future,
     pin::Pin,
@@ -27,6 +27,10 @@ impl<Tx> Sealer<Tx> for Sized<Tx>
 where
     Tx: Send + Sync + Clone + 'static,
 {
+    fn name(&self) -> &'static str {
+        "sized"
+    }
+
     /// Resets the sealer with current size as 0 and returns all the
     /// transactions
     fn seal(&mut self) -> Vec<Tx> {
@@ -42,6 +46,13 @@ where
         self.current_size += tx_size;
         self.txs.push(tx);
     }
+
+    fn reconfigure(
+        &mut self,
+        params: &SealerParams,
+    ) {
+        self.max_size = params.size;
+    }
 }
 
 impl<Tx> Unpin for Sized<Tx> {}
@@ -1,4 +1,4 @@
-use crate::Sealer;
+use crate::{Sealer, SealerParams};
 use std::{
     future::Future,
     pin::Pin,
@@ -31,6 +31,10 @@ impl<Tx> Sealer<Tx> for Timed<Tx>
 where
     Tx: Send + Sync + Clone + 'static,
 {
+    fn name(&self) -> &'static str {
+        "timed"
+    }
+
     /// Resets the timer and returns all the transactions
     fn seal(&mut self) -> Vec<Tx> {
         self.reset_timer();
@@ -44,6 +48,19 @@ where
     ) {
         self.txs.push(tx);
     }
+
+    /// A longer timeout applies from the next seal on, a shorter one right
+    /// away
+    fn reconfigure(
+        &mut self,
+        params: &SealerParams,
+    ) {
+        self.timeout = params.timeout;
+        let deadline = Instant::now() + self.timeout;
+        if deadline < self.timer.deadline() {
+            self.timer.as_mut().reset(deadline);
+        }
+    }
 }
 
 impl<Tx> Unpin for Timed<Tx> {}
@@ -0,0 +1,392 @@
+use crate::{MempoolNetwork, Transaction};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use futures::{stream::BoxStream, Stream, StreamExt};
+use net_common::Message;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fmt::Debug,
+    marker::PhantomData,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Notify,
+    },
+    time::{self, Instant},
+};
+
+/// How the simulated network treats the messages
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    /// Seeds the delays and the drops, so that runs can be replayed
+    pub seed: u64,
+    /// Every message is delayed by a random duration in `[min_delay, max_delay]`
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// The probability that a message is lost
+    pub drop_rate: f64,
+    /// Whether messages may overtake the earlier ones on the same link. By
+    /// default, links are FIFO, like TCP connections.
+    pub reorder: bool,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            drop_rate: 0.0,
+            reorder: false,
+        }
+    }
+}
+
+/// What happened to the messages so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimStats {
+    pub sent: u64,
+    pub delivered: u64,
+    /// Lost at random, or between partitions
+    pub dropped: u64,
+    /// Sent to an address where nothing listens
+    pub unreachable: u64,
+}
+
+/// A message on its way
+struct InFlight {
+    at: Instant,
+    /// Breaks the ties between the messages due at the same time
+    seq: u64,
+    to: SocketAddr,
+    data: Bytes,
+}
+
+impl PartialEq for InFlight {
+    fn eq(
+        &self,
+        other: &Self,
+    ) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for InFlight {}
+
+impl PartialOrd for InFlight {
+    fn partial_cmp(
+        &self,
+        other: &Self,
+    ) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InFlight {
+    fn cmp(
+        &self,
+        other: &Self,
+    ) -> std::cmp::Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+struct State {
+    config: SimConfig,
+    rng: StdRng,
+    seq: u64,
+    in_flight: BinaryHeap<Reverse<InFlight>>,
+    /// The last delivery on every link, to keep them FIFO
+    last_delivery: FnvHashMap<(IpAddr, SocketAddr), Instant>,
+    /// Where the receivers listen
+    inboxes: FnvHashMap<SocketAddr, UnboundedSender<Bytes>>,
+    /// The partition of every host. Hosts that are not listed are in the same
+    /// partition as each other.
+    partitions: FnvHashMap<IpAddr, usize>,
+    stats: SimStats,
+}
+
+impl State {
+    fn connected(
+        &self,
+        from: IpAddr,
+        to: IpAddr,
+    ) -> bool {
+        self.partitions.get(&from) == self.partitions.get(&to)
+    }
+}
+
+/// An in-process network, where every host is an IP address
+///
+/// Messages are delayed, lost and reordered at random, as set by `SimConfig`,
+/// and hosts can be partitioned. With the same seed, and tokio's paused time,
+/// the same sends lead to the same deliveries.
+#[derive(Clone)]
+pub struct SimNetwork {
+    state: Arc<Mutex<State>>,
+    /// Wakes the delivery task up when a message is sent
+    notify: Arc<Notify>,
+}
+
+impl SimNetwork {
+    /// Creates the network, and spawns the task that delivers its messages for
+    /// as long as the runtime runs
+    pub fn new(config: SimConfig) -> Self {
+        let network = Self {
+            state: Arc::new(Mutex::new(State {
+                rng: StdRng::seed_from_u64(config.seed),
+                config,
+                seq: 0,
+                in_flight: BinaryHeap::new(),
+                last_delivery: FnvHashMap::default(),
+                inboxes: FnvHashMap::default(),
+                partitions: FnvHashMap::default(),
+                stats: SimStats::default(),
+            })),
+            notify: Arc::new(Notify::new()),
+        };
+        tokio::spawn(network.clone().deliver());
+        network
+    }
+
+    /// Sends to the receivers of `peers`, from `host`, like `TcpSimpleSender`
+    pub fn sender<Id, M>(
+        &self,
+        host: IpAddr,
+        peers: FnvHashMap<Id, SocketAddr>,
+    ) -> SimSender<Id, M> {
+        SimSender {
+            network: self.clone(),
+            host,
+            peers,
+            _x: PhantomData,
+        }
+    }
+
+    /// Receives the messages sent to `addr`, like `TcpReceiver`
+    ///
+    /// A new receiver on the same address replaces the previous one.
+    pub fn receiver<M>(
+        &self,
+        addr: SocketAddr,
+    ) -> SimReceiver<M> {
+        let (tx_inbox, rx_inbox) = unbounded_channel();
+        self.state.lock().unwrap().inboxes.insert(addr, tx_inbox);
+        SimReceiver {
+            rx_inbox,
+            _x: PhantomData,
+        }
+    }
+
+    /// Splits the hosts in `groups`, which can no longer talk to each other.
+    /// The hosts left out form another group.
+    pub fn partition(
+        &self,
+        groups: &[Vec<IpAddr>],
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.partitions = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, group)| group.iter().map(move |host| (*host, i)))
+            .collect();
+    }
+
+    /// Lets every host talk to every other again
+    pub fn heal(&self) {
+        self.state.lock().unwrap().partitions.clear();
+    }
+
+    pub fn stats(&self) -> SimStats {
+        self.state.lock().unwrap().stats
+    }
+
+    fn send(
+        &self,
+        from: IpAddr,
+        to: SocketAddr,
+        data: Bytes,
+    ) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.stats.sent += 1;
+        // Draw every random value, so that a message does not change the fate
+        // of the next ones by being dropped
+        let lost = state.rng.gen_bool(state.config.drop_rate);
+        let delay = state
+            .rng
+            .gen_range(state.config.min_delay..=state.config.max_delay);
+        if lost || !state.connected(from, to.ip()) {
+            state.stats.dropped += 1;
+            return;
+        }
+        let mut at = Instant::now() + delay;
+        if !state.config.reorder {
+            if let Some(last) = state.last_delivery.get(&(from, to)) {
+                at = at.max(*last);
+            }
+            state.last_delivery.insert((from, to), at);
+        }
+        state.seq += 1;
+        let seq = state.seq;
+        state.in_flight.push(Reverse(InFlight { at, seq, to, data }));
+        self.notify.notify_one();
+    }
+
+    /// Hands the messages to their receivers, when they are due
+    async fn deliver(self) {
+        loop {
+            let next = self
+                .state
+                .lock()
+                .unwrap()
+                .in_flight
+                .peek()
+                .map(|Reverse(msg)| msg.at);
+            match next {
+                Some(at) if at <= Instant::now() => self.deliver_due(),
+                Some(at) => {
+                    tokio::select! {
+                        _ = time::sleep_until(at) => self.deliver_due(),
+                        _ = self.notify.notified() => {}
+                    }
+                }
+                None => self.notify.notified().await,
+            }
+        }
+    }
+
+    fn deliver_due(&self) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        while state.in_flight.peek().is_some_and(|Reverse(msg)| msg.at <= now) {
+            let Reverse(msg) = state.in_flight.pop().unwrap();
+            let delivered = match state.inboxes.get(&msg.to) {
+                Some(inbox) => inbox.send(msg.data).is_ok(),
+                None => false,
+            };
+            if delivered {
+                state.stats.delivered += 1;
+            } else {
+                log::debug!("Nothing listens on {}", msg.to);
+                state.stats.unreachable += 1;
+            }
+        }
+    }
+}
+
+/// Sends messages over a `SimNetwork`, with the interface of
+/// `TcpSimpleSender`
+pub struct SimSender<Id, M> {
+    network: SimNetwork,
+    host: IpAddr,
+    peers: FnvHashMap<Id, SocketAddr>,
+    _x: PhantomData<fn() -> M>,
+}
+
+impl<Id, M> Clone for SimSender<Id, M>
+where
+    Id: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            network: self.network.clone(),
+            host: self.host,
+            peers: self.peers.clone(),
+            _x: PhantomData,
+        }
+    }
+}
+
+impl<Id, M> SimSender<Id, M>
+where
+    Id: Debug + Eq + std::hash::Hash,
+{
+    pub fn get_peers(&self) -> &FnvHashMap<Id, SocketAddr> {
+        &self.peers
+    }
+
+    /// Sends `data` to `recipient`. Like TCP, this succeeds even if the
+    /// message is lost on the way.
+    pub async fn send(
+        &mut self,
+        recipient: Id,
+        data: Bytes,
+    ) -> Result<()> {
+        let addr = self
+            .peers
+            .get(&recipient)
+            .ok_or_else(|| anyhow!("Unknown peer {:?}", recipient))?;
+        self.network.send(self.host, *addr, data);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<Id, M> MempoolNetwork<Id> for SimSender<Id, M>
+where
+    Id: Debug + Clone + Eq + std::hash::Hash + Send + Sync + 'static,
+    M: 'static,
+{
+    fn fork(&self) -> Self {
+        self.clone()
+    }
+
+    fn peers(&self) -> Vec<Id> {
+        self.peers.keys().cloned().collect()
+    }
+
+    async fn send(
+        &mut self,
+        recipient: Id,
+        data: Bytes,
+    ) -> Result<()> {
+        SimSender::send(self, recipient, data).await
+    }
+
+    fn receive<T>(
+        &self,
+        addr: SocketAddr,
+    ) -> BoxStream<'static, Result<T>>
+    where
+        T: Transaction,
+    {
+        self.network
+            .receiver::<T>(addr)
+            .map(|result| result.map_err(|_| anyhow!("Failed to deserialize a message")))
+            .boxed()
+    }
+}
+
+/// The messages sent to an address of a `SimNetwork`, with the interface of
+/// `TcpReceiver`
+pub struct SimReceiver<M> {
+    rx_inbox: UnboundedReceiver<Bytes>,
+    _x: PhantomData<fn() -> M>,
+}
+
+impl<M> Stream for SimReceiver<M>
+where
+    M: Message,
+{
+    type Item = Result<M, M::DeserializationError>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.rx_inbox
+            .poll_recv(cx)
+            .map(|data| data.map(|data| M::from_bytes(&data)))
+    }
+}
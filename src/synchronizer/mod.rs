@@ -1,28 +1,102 @@
-use crate::{Batch, BatchHash, ConsensusMempoolMsg, MempoolMsg, Transaction};
+use crate::{
+    tx_hash, Batch, BatchHash, BatchHeader, BatchIndex, BatchStore, Config, ConsensusMempoolMsg,
+    MempoolMsg, MempoolNetwork, Metrics, Processed, Transaction, TxHash, TxReceiveHandler,
+};
+use anyhow::{anyhow, Result};
 use bytes::Bytes;
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 use futures::{stream::FuturesUnordered, StreamExt};
-use libcrypto::hash::Hash;
 use rand::seq::SliceRandom;
-use serde::Serialize;
+use serde::{de::DeserializeOwned, Serialize};
 use std::fmt::Debug;
 use std::time::Duration;
 use tcp_sender::TcpSimpleSender;
 use tokio::{
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot, watch,
+    },
     time::{sleep, Instant},
 };
+use tracing::Instrument;
+pub(crate) use retry::*;
 pub use waiter::*;
 
+mod retry;
 mod waiter;
 
-pub struct Synchronizer<Id, Round, Tx, Storage> {
+/// Queries answered by the synchronizer
+pub enum SynchronizerQuery<Tx> {
+    /// The digests of the batches we are waiting for
+    Pending(oneshot::Sender<Vec<BatchHash<Tx>>>),
+    /// Replies whether we are waiting for a batch
+    IsPending(BatchHash<Tx>, oneshot::Sender<bool>),
+    /// The state of the synchronizer
+    Status(oneshot::Sender<SyncStatus>),
+    /// What we observed about every peer we synchronized batches from
+    Peers(oneshot::Sender<Vec<PeerHealth>>),
+    /// Deletes the batches older than the gc round right away, and replies
+    /// with their number
+    Gc(oneshot::Sender<usize>),
+    /// Stops waiting for a batch, and replies whether we were waiting for it
+    Drop(BatchHash<Tx>, oneshot::Sender<bool>),
+}
+
+/// A summary of the state of the synchronizer
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncStatus {
+    /// The latest round that ended
+    pub round: String,
+    /// The latest gc round
+    pub gc_round: String,
+    /// The number of batches we are waiting for
+    pub pending: usize,
+    /// The number of consensus requests waiting for missing batches
+    pub sync_requests: usize,
+    /// The number of batches in the store
+    pub stored: usize,
+    /// The number of batches that are kept regardless of their round
+    pub pinned: usize,
+    /// The number of our batches that are not committed yet
+    pub uncommitted: usize,
+}
+
+/// What we observed about a peer while synchronizing batches from it
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerHealth {
+    pub peer: String,
+    /// The number of batches that arrived after asking this peer
+    pub successes: u64,
+    /// The number of requests that this peer did not answer in time
+    pub failures: u64,
+    /// The (smoothed) fraction of requests answered by this peer
+    pub success_rate: f64,
+    /// The (moving average of the) time it took to get a batch, in ms
+    pub latency_ms: Option<f64>,
+}
+
+pub struct Synchronizer<Id, Round, Tx, Storage, Net = TcpSimpleSender<Id, MempoolMsg<Id, Tx>>> {
     /// Id of this node
     my_name: Id,
 
     /// This is the channel used to get messages from the consensus layer
     rx_consensus: UnboundedReceiver<ConsensusMempoolMsg<Id, Round, Tx>>,
 
+    /// This is the channel used to learn about batches written to the store
+    rx_processed: UnboundedReceiver<Processed<Tx>>,
+
+    /// This is the channel used to learn about corrupted batches, that we need
+    /// to fetch again
+    rx_resync: UnboundedReceiver<BatchHash<Tx>>,
+
+    /// This is the channel used to answer queries for the pending or stored
+    /// digests
+    rx_query: UnboundedReceiver<SynchronizerQuery<Tx>>,
+
+    /// This is the channel used to learn about config updates. The gc depth,
+    /// the sync retry parameters and the re-inject depth follow them.
+    rx_config: watch::Receiver<Config<Round>>,
+
     /// The number of history rounds we need to maintain in the storage
     gc_depth: Round,
 
@@ -32,18 +106,61 @@ pub struct Synchronizer<Id, Round, Tx, Storage> {
     /// Keeps the digests (of batches) that are waiting to be processed by the
     /// consensus. Their processing will resume when we get the missing
     /// batches in the store or we no longer need them. It also keeps the
-    /// round number and a timestamp (`u128`) of each request we sent.
-    pending: FnvHashMap<BatchHash<Tx>, (Round, UnboundedSender<()>, Instant)>,
+    /// round number and the retry state of each request we sent.
+    pending: FnvHashMap<BatchHash<Tx>, PendingBatch<Id, Round>>,
+
+    /// Requests from consensus that are waiting for missing batches, along
+    /// with the digests that have not arrived yet
+    sync_requests: Vec<(FnvHashSet<BatchHash<Tx>>, oneshot::Sender<Result<()>>)>,
+
+    /// The round at which every stored batch was last sequenced or
+    /// referenced. Batches older than the gc round are deleted from the store.
+    batch_rounds: FnvHashMap<BatchHash<Tx>, Round>,
+
+    /// Batches that consensus asked us to keep, regardless of their round
+    pinned: FnvHashSet<BatchHash<Tx>>,
+
+    /// Batches that we sealed and handed to consensus, but that are not
+    /// committed yet, along with the round at which they were stored.
+    /// Re-injecting the batches of other nodes is the job of their author.
+    uncommitted: FnvHashMap<BatchHash<Tx>, Round>,
+
+    /// Batches that are final, along with the round at which they were
+    /// committed
+    committed: FnvHashMap<BatchHash<Tx>, Round>,
+
+    /// Transactions of the committed batches. These are never re-injected.
+    committed_txs: FnvHashMap<TxHash<Tx>, Round>,
+
+    /// The number of rounds after which the transactions of uncommitted
+    /// batches are re-injected. `None` disables re-injection.
+    reinject_depth: Option<Round>,
+
+    /// Used to re-inject transactions into the batcher
+    tx_handler: TxReceiveHandler<Tx>,
 
     /// Used to send sync messages to the network
-    mempool_sender: TcpSimpleSender<Id, MempoolMsg<Id, Tx>>,
+    mempool_sender: Net,
 
     /// Storage to clean
-    storage: Storage,
+    storage: BatchStore<Storage, Tx>,
+
+    /// The index of the stored batches, that the scrubber walks. `None` if it
+    /// could not be loaded.
+    index: Option<BatchIndex<Storage, Tx>>,
 
-    /// Synchronization wait time
+    /// What we observed about each peer while synchronizing batches
+    peer_stats: FnvHashMap<Id, PeerStats>,
+
+    /// Synchronization wait time, before the first retry
     wait_time: Duration,
 
+    /// The maximum wait time between two retries
+    max_wait_time: Duration,
+
+    /// The number of retries after which we give up on a batch
+    max_attempts: usize,
+
     /// Current round
     round: Round,
 
@@ -53,39 +170,70 @@ pub struct Synchronizer<Id, Round, Tx, Storage> {
     /// The number of nodes to send the request to, after failing to get it from
     /// the original sender
     sync_retry_nodes: usize,
+
+    metrics: Metrics,
 }
 
-impl<Id, Round, Tx, Storage> Synchronizer<Id, Round, Tx, Storage>
+impl<Id, Round, Tx, Storage, Net> Synchronizer<Id, Round, Tx, Storage, Net>
 where
     Tx: Transaction,
     Id: Debug + Clone + Eq + std::hash::Hash + Send + Sync + Serialize + 'static,
-    Round: crate::Round,
+    Round: crate::Round + DeserializeOwned,
     Storage: libstorage::Store,
+    Net: MempoolNetwork<Id>,
 {
     #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         my_name: Id,
         rx_consensus: UnboundedReceiver<ConsensusMempoolMsg<Id, Round, Tx>>,
-        gc_depth: Round,
-        mempool_sender: TcpSimpleSender<Id, MempoolMsg<Id, Tx>>,
-        storage: Storage,
-        wait_time: Duration,
+        rx_processed: UnboundedReceiver<Processed<Tx>>,
+        rx_query: UnboundedReceiver<SynchronizerQuery<Tx>>,
+        rx_resync: UnboundedReceiver<BatchHash<Tx>>,
+        mut rx_config: watch::Receiver<Config<Round>>,
+        mempool_sender: Net,
+        storage: BatchStore<Storage, Tx>,
         all_ids: Vec<Id>,
-        sync_retry_nodes: usize,
+        tx_batcher: UnboundedSender<(Tx, usize)>,
+        metrics: Metrics,
     ) {
+        let config = rx_config.borrow_and_update().clone();
         tokio::spawn(async move {
+            let index = match BatchIndex::load(storage.clone()).await {
+                Ok(index) => Some(index),
+                Err(e) => {
+                    log::error!("Failed to load the index of the stored batches: {}", e);
+                    None
+                }
+            };
             Self {
                 my_name,
                 rx_consensus,
-                gc_depth,
+                rx_processed,
+                rx_query,
+                rx_resync,
+                rx_config,
+                gc_depth: config.gc_depth,
                 latest_gc_round: Round::MIN,
                 pending: FnvHashMap::default(),
+                sync_requests: Vec::new(),
+                batch_rounds: FnvHashMap::default(),
+                pinned: FnvHashSet::default(),
+                uncommitted: FnvHashMap::default(),
+                committed: FnvHashMap::default(),
+                committed_txs: FnvHashMap::default(),
+                reinject_depth: config.reinject_depth,
+                tx_handler: TxReceiveHandler::new(tx_batcher, metrics.clone()),
                 mempool_sender,
                 storage,
-                wait_time,
+                index,
+                peer_stats: FnvHashMap::default(),
+                wait_time: config.sync_retry_delay,
+                max_wait_time: config.sync_retry_max_delay,
+                max_attempts: config.sync_retry_max_attempts,
                 round: Round::MIN,
                 all_ids,
-                sync_retry_nodes,
+                sync_retry_nodes: config.sync_retry_nodes,
+                metrics,
             }
             .run()
             .await;
@@ -99,10 +247,24 @@ where
         let mut timer = Box::pin(timer);
 
         loop {
+            self.metrics.sync_pending.set(self.pending.len() as i64);
+            self.metrics.batches_tracked.set(self.batch_rounds.len() as i64);
+
             tokio::select! {
                 // Handle messages from consensus
                 Some(message) = self.rx_consensus.recv() => match message {
-                    ConsensusMempoolMsg::UnknownBatch(source, hashes) => {
+                    ConsensusMempoolMsg::UnknownBatch(source, hashes, holders, tx_done) => {
+                        // Consensus referenced these batches, so keep the ones we have around
+                        for hash in &hashes {
+                            if let Some(r) = self.batch_rounds.get_mut(hash) {
+                                *r = self.round;
+                            }
+                            // Remember who else holds the batches we are already waiting for
+                            if let Some(batch) = self.pending.get_mut(hash) {
+                                batch.add_holders(&holders);
+                            }
+                        }
+
                         // Check pending and obtain all hashes for which we have not already requested a batch
                         let missing = hashes.iter()
                             .filter_map(|hash| {
@@ -116,16 +278,37 @@ where
                         for missing_hash in &missing {
                             log::debug!("Request sync for {}", missing_hash);
                             // Add the digest to the waiter.
+                            let span = tracing::info_span!("sync_batch", batch = %missing_hash, source = ?source);
+                            span.in_scope(|| tracing::debug!(holders = ?holders, "Requesting batch"));
                             let (tx_cancel, rx_cancel) = unbounded_channel();
-                            let fut = wait::<Storage, Batch<Tx>>(self.storage.clone(), missing_hash.clone(), rx_cancel);
-                            sync_waiting.push(fut);
-                            self.pending.insert(missing_hash.clone(), (self.round, tx_cancel, Instant::now()));
+                            let fut = wait(self.storage.clone(), missing_hash.clone(), rx_cancel);
+                            sync_waiting.push(fut.instrument(span.clone()));
+                            self.pending.insert(
+                                missing_hash.clone(),
+                                PendingBatch::new(
+                                    self.round,
+                                    tx_cancel,
+                                    source.clone(),
+                                    holders.clone(),
+                                    self.wait_time,
+                                )
+                                .with_span(span),
+                            );
+                        }
+
+                        // Every digest is now pending, let consensus know once all of them arrive
+                        let waiting: FnvHashSet<_> = hashes.into_iter().collect();
+                        if waiting.is_empty() {
+                            let _ = tx_done.send(Ok(()));
+                        } else {
+                            self.sync_requests.push((waiting, tx_done));
                         }
 
                         // Send sync request to a single node. If this fails, we will send it
                         // to other nodes when a timer times out.
                         let message = MempoolMsg::<Id, Tx>::RequestBatch(self.my_name.clone(), missing);
                         let serialized = Bytes::from(bincode::serialize(&message).unwrap());
+                        self.metrics.sync_requests_sent.inc();
                         if let Err(e) = self.mempool_sender.send(source, serialized).await {
                             log::warn!("Synchronizer send error: {}", e);
                         }
@@ -133,34 +316,175 @@ where
 
                     ConsensusMempoolMsg::End(round) => {
                         self.round = round;
+                        self.reinject().await;
+                        if self.advance_gc_round() {
+                            self.cleanup().await;
+                        }
+                    }
 
-                        if self.latest_gc_round > round {
-                            log::debug!("Already cleaned {:?}", round);
-                            continue;
+                    ConsensusMempoolMsg::Committed(round, hashes) => {
+                        for hash in hashes {
+                            self.metrics.latency.committed(&hash);
+                            self.uncommitted.remove(&hash);
+                            // Remember the transactions so that we never re-inject them, even
+                            // if re-injection is only enabled later on. GC bounds this map.
+                            if let Some(batch) = self.read_batch(&hash).await {
+                                for tx in &batch.payload {
+                                    self.committed_txs.insert(tx_hash(tx), round);
+                                }
+                            }
+                            self.committed.insert(hash, round);
+                        }
+                    }
+
+                    ConsensusMempoolMsg::Pin(hashes) => {
+                        self.pinned.extend(hashes);
+                    }
+
+                    ConsensusMempoolMsg::Release(hashes) => {
+                        for hash in &hashes {
+                            self.pinned.remove(hash);
                         }
+                    }
+                },
 
-                        self.latest_gc_round = round - self.gc_depth;
-                        for (r, handler, _) in self.pending.values() {
-                            if r < &self.latest_gc_round {
-                                let _ = handler.send(());
+                // A batch was written to the store, remember when it was sealed
+                Some(Processed { digest, own, header }) = self.rx_processed.recv() => {
+                    // Re-injection and gc go by the same round, so that a batch
+                    // is re-injected before it is collected
+                    let round = self.sealed_round(&digest, header.as_ref());
+                    if own && !self.committed.contains_key(&digest) {
+                        self.uncommitted.insert(digest.clone(), round);
+                    }
+                    // Let the scrubber find the batch, even after a restart
+                    if let Some(index) = &mut self.index {
+                        if let Err(e) = index.add(&digest).await {
+                            log::warn!("Failed to index batch {}: {}", digest, e);
+                        }
+                    }
+                    self.batch_rounds.insert(digest, round);
+                },
+
+                // A stored batch was corrupted, fetch it again from a random peer
+                Some(hash) = self.rx_resync.recv() => {
+                    if self.pending.contains_key(&hash) {
+                        continue;
+                    }
+                    let source = {
+                        let peers: Vec<&Id> = self.all_ids.iter().filter(|id| *id != &self.my_name).collect();
+                        match peers.choose(&mut rand::thread_rng()) {
+                            Some(peer) => (*peer).clone(),
+                            None => {
+                                log::warn!("No peer to re-sync batch {} from", hash);
+                                continue;
                             }
                         }
-                        self.pending.retain(|_, (r, _, _)| r > &mut self.latest_gc_round);
+                    };
+
+                    log::debug!("Request re-sync for {}", hash);
+                    let span = tracing::info_span!("sync_batch", batch = %hash, source = ?source, resync = true);
+                    span.in_scope(|| tracing::debug!("Requesting corrupted batch again"));
+                    let (tx_cancel, rx_cancel) = unbounded_channel();
+                    let fut = wait(self.storage.clone(), hash.clone(), rx_cancel);
+                    sync_waiting.push(fut.instrument(span.clone()));
+                    self.pending.insert(
+                        hash.clone(),
+                        PendingBatch::new(self.round, tx_cancel, source.clone(), Vec::new(), self.wait_time)
+                            .with_span(span),
+                    );
+
+                    let message = MempoolMsg::<Id, Tx>::RequestBatch(self.my_name.clone(), vec![hash]);
+                    let serialized = Bytes::from(bincode::serialize(&message).unwrap());
+                    self.metrics.sync_requests_sent.inc();
+                    if let Err(e) = self.mempool_sender.send(source, serialized).await {
+                        log::warn!("Synchronizer send error: {}", e);
+                    }
+                },
+
+                // Someone wants to know which batches we are still waiting for, or have
+                Some(query) = self.rx_query.recv() => match query {
+                    SynchronizerQuery::Pending(tx_reply) => {
+                        let _ = tx_reply.send(self.pending.keys().cloned().collect());
+                    }
+                    SynchronizerQuery::IsPending(hash, tx_reply) => {
+                        let _ = tx_reply.send(self.pending.contains_key(&hash));
+                    }
+                    SynchronizerQuery::Status(tx_reply) => {
+                        let _ = tx_reply.send(SyncStatus {
+                            round: self.round.to_string(),
+                            gc_round: self.latest_gc_round.to_string(),
+                            pending: self.pending.len(),
+                            sync_requests: self.sync_requests.len(),
+                            stored: self.batch_rounds.len(),
+                            pinned: self.pinned.len(),
+                            uncommitted: self.uncommitted.len(),
+                        });
+                    }
+                    SynchronizerQuery::Peers(tx_reply) => {
+                        let peers = self.peer_stats
+                            .iter()
+                            .map(|(peer, stats)| stats.health(format!("{:?}", peer)))
+                            .collect();
+                        let _ = tx_reply.send(peers);
+                    }
+                    SynchronizerQuery::Gc(tx_reply) => {
+                        self.reinject().await;
+                        self.advance_gc_round();
+                        let collected = self.cleanup().await;
+                        let _ = tx_reply.send(collected);
+                    }
+                    SynchronizerQuery::Drop(hash, tx_reply) => {
+                        let dropped = match self.pending.remove(&hash) {
+                            Some(batch) => {
+                                log::warn!("Dropping the sync request for batch {}", hash);
+                                batch.span.in_scope(|| tracing::warn!("Dropped"));
+                                let _ = batch.cancel.send(());
+                                self.fail_sync_requests(&hash, "was dropped");
+                                true
+                            }
+                            None => false,
+                        };
+                        let _ = tx_reply.send(dropped);
                     }
                 },
 
+                // The config was updated, the new parameters apply from now on
+                Ok(()) = self.rx_config.changed() => {
+                    let config = self.rx_config.borrow_and_update().clone();
+                    self.gc_depth = config.gc_depth;
+                    self.wait_time = config.sync_retry_delay;
+                    self.max_wait_time = config.sync_retry_max_delay;
+                    self.max_attempts = config.sync_retry_max_attempts;
+                    self.sync_retry_nodes = config.sync_retry_nodes;
+                    self.reinject_depth = config.reinject_depth;
+                },
+
                 // Some request which we were waiting for has been resolved
-                Some(result) = sync_waiting.next() => match result {
-                    Ok(None) => {
+                Some((hash, result)) = sync_waiting.next() => match result {
+                    Ok(false) => {
                         log::debug!("Sync request was cancelled!");
                     },
-                    Ok(Some(hash_vec)) => {
+                    Ok(true) => {
                         // We got the batch, remove it from the pending list.
-                        let hash: Hash<Batch<Tx>> = hash_vec[0..32].try_into().unwrap();
-                        self.pending.remove(&hash);
+                        if let Some(mut batch) = self.pending.remove(&hash) {
+                            log::debug!("Synced batch {} after {} retries", hash, batch.attempts);
+                            batch.span.in_scope(|| tracing::debug!(retries = batch.attempts, "Synced batch"));
+                            batch.delivered(&mut self.peer_stats);
+                            self.metrics.sync_attempts.observe_value(batch.attempts as f64);
+                        }
+                        // If this was one of ours, fetched again after it was corrupted, it
+                        // still awaits its commit
+                        self.notify_sync_requests(&hash);
                     },
                     Err(e) => {
-                        log::error!("Got error while synchronizing: {}", e);
+                        // Nothing waits for the batch anymore, so give up on it rather than
+                        // leave its requests hanging
+                        log::error!("Got error while synchronizing batch {}: {}", hash, e);
+                        if let Some(batch) = self.pending.remove(&hash) {
+                            self.metrics.sync_failures.inc();
+                            batch.span.in_scope(|| tracing::warn!(error = %e, "Failed to wait for batch"));
+                        }
+                        self.fail_sync_requests(&hash, "could not be read from the store");
                     },
                 },
 
@@ -168,41 +492,255 @@ where
                 () = (&mut timer) => {
                     // We optimistically sent sync requests to a single node. If this timer triggers,
                     // it means we were wrong to trust it. We are done waiting for a reply and we now
-                    // broadcast the request to a bunch of other nodes (selected at random).
+                    // send the request to other nodes (that we did not ask yet), backing off
+                    // exponentially on every attempt.
                     let now = Instant::now();
-                    let mut retry = Vec::new();
-                    for (hash, (_, _, req_time)) in &self.pending {
-                        if now - self.wait_time > *req_time {
-                            log::debug!("Requesting sync for batch {:?} (retry)", hash);
-                            retry.push(hash.clone());
+                    let mut retries: FnvHashMap<Id, Vec<BatchHash<Tx>>> = FnvHashMap::default();
+                    let mut failed = Vec::new();
+                    for (hash, batch) in self.pending.iter_mut() {
+                        if batch.next_retry > now {
+                            continue;
+                        }
+                        if batch.attempts >= self.max_attempts {
+                            failed.push(hash.clone());
+                            continue;
+                        }
+                        batch.backoff(self.wait_time, self.max_wait_time, &mut self.peer_stats);
+                        self.metrics.sync_retries.inc();
+                        log::debug!("Requesting sync for batch {:?} (retry {})", hash, batch.attempts);
+                        let peers = batch.select_peers(
+                            &self.all_ids,
+                            &self.my_name,
+                            self.sync_retry_nodes,
+                            &self.peer_stats,
+                        );
+                        batch.span.in_scope(|| tracing::debug!(attempt = batch.attempts, peers = ?peers, "Retrying"));
+                        for peer in peers {
+                            retries.entry(peer).or_default().push(hash.clone());
                         }
                     }
-                    if !retry.is_empty() {
-                        let message = MempoolMsg::<Id, Tx>::RequestBatch(self.my_name.clone(), retry);
-                        let serialized = Bytes::from(bincode::serialize(&message).unwrap());
 
-                        // Select random peers (replacing the old randcast)
-                        let selected: Vec<Id> = {
-                            let mut rng = rand::thread_rng();
-                            let mut candidates = self.all_ids.clone();
-                            candidates.shuffle(&mut rng);
-                            candidates.into_iter()
-                                .take(self.sync_retry_nodes)
-                                .collect()
-                        };
+                    for hash in failed {
+                        if let Some(batch) = self.pending.remove(&hash) {
+                            log::warn!("Giving up on batch {} after {} retries", hash, batch.attempts);
+                            self.metrics.sync_failures.inc();
+                            self.metrics.sync_attempts.observe_value(batch.attempts as f64);
+                            batch.span.in_scope(|| tracing::warn!(retries = batch.attempts, "Gave up on batch"));
+                            let _ = batch.cancel.send(());
+                        }
+                        self.fail_sync_requests(&hash, "could not be synchronized");
+                    }
 
-                        for peer in selected {
-                            if let Err(e) = self.mempool_sender.send(peer, serialized.clone()).await {
-                                log::warn!("Synchronizer retry send error: {}", e);
-                            }
+                    for (peer, retry) in retries {
+                        let message = MempoolMsg::<Id, Tx>::RequestBatch(self.my_name.clone(), retry);
+                        let serialized = Bytes::from(bincode::serialize(&message).unwrap());
+                        self.metrics.sync_requests_sent.inc();
+                        if let Err(e) = self.mempool_sender.send(peer, serialized).await {
+                            log::warn!("Synchronizer retry send error: {}", e);
                         }
                     }
 
-                    // Reschedule the timer.
-                    timer.as_mut().reset(Instant::now() + self.wait_time);
+                    // Reschedule the timer for the earliest retry, but check again within
+                    // `wait_time` in case new requests come in.
+                    let next = self.pending
+                        .values()
+                        .map(|batch| batch.next_retry)
+                        .min()
+                        .unwrap_or(now + self.wait_time)
+                        .min(now + self.wait_time);
+                    timer.as_mut().reset(next);
                 }
             }
         }
         // log::warn!("Synchronizer is shutting down!");
     }
+
+    /// Removes a batch that arrived from the sync requests, and notifies the
+    /// requests that are no longer waiting for any batch
+    fn notify_sync_requests(
+        &mut self,
+        hash: &BatchHash<Tx>,
+    ) {
+        let (done, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.sync_requests)
+            .into_iter()
+            .map(|(mut missing, tx_done)| {
+                missing.remove(hash);
+                (missing, tx_done)
+            })
+            .partition(|(missing, _)| missing.is_empty());
+        self.sync_requests = waiting;
+        for (_, tx_done) in done {
+            let _ = tx_done.send(Ok(()));
+        }
+    }
+
+    /// Fails the sync requests that are waiting for a batch that will no
+    /// longer be synchronized
+    fn fail_sync_requests(
+        &mut self,
+        hash: &BatchHash<Tx>,
+        reason: &str,
+    ) {
+        let (failed, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.sync_requests)
+            .into_iter()
+            .partition(|(missing, _)| missing.contains(hash));
+        self.sync_requests = waiting;
+        for (_, tx_done) in failed {
+            let _ = tx_done.send(Err(anyhow!("Batch {} {}", hash, reason)));
+        }
+    }
+
+    /// Feeds the transactions of the batches that were not committed within
+    /// `reinject_depth` rounds back into the batcher. Batches about to be
+    /// garbage collected are re-injected right away, even if `reinject_depth`
+    /// is deeper than `gc_depth`, since they would never be otherwise.
+    async fn reinject(&mut self) {
+        let limit = match self.reinject_depth {
+            Some(depth) => self.round.checked_sub(depth),
+            None => return,
+        };
+        // The gc round that `advance_gc_round` is about to move to
+        let gc_round = (self.gc_depth != Round::MIN)
+            .then(|| self.round.saturating_sub(self.gc_depth));
+
+        let mut orphans = Vec::new();
+        self.uncommitted.retain(|hash, r| {
+            let expired = limit.is_some_and(|limit| *r <= limit);
+            let collected = gc_round.is_some_and(|gc_round| *r < gc_round);
+            if expired || collected {
+                orphans.push(hash.clone());
+                return false;
+            }
+            true
+        });
+
+        let my_author = bincode::serialize(&self.my_name).expect("Failed to serialize my name");
+        let mut reinjected = FnvHashSet::default();
+        for hash in orphans {
+            let batch = match self.read_batch(&hash).await {
+                Some(batch) => batch,
+                None => continue,
+            };
+            // Batches sealed by other nodes are theirs to re-inject
+            if let Some(header) = &batch.header {
+                if header.author != my_author {
+                    continue;
+                }
+            }
+            log::debug!("Re-injecting transactions from batch {}", hash);
+            for tx in batch.payload {
+                let digest = tx_hash(&tx);
+                if self.committed_txs.contains_key(&digest) || !reinjected.insert(digest) {
+                    continue;
+                }
+                self.tx_handler.reinject(tx);
+                self.metrics.transactions_reinjected.inc();
+            }
+        }
+    }
+
+    /// The round in which a batch was sealed, according to its header, and
+    /// the current round otherwise. Rounds ahead of ours are capped, so that a
+    /// bogus header cannot keep a batch from being collected.
+    fn sealed_round(
+        &self,
+        digest: &BatchHash<Tx>,
+        header: Option<&BatchHeader>,
+    ) -> Round {
+        match header.map(|header| header.round::<Round>()) {
+            Some(Ok(round)) => round.min(self.round),
+            Some(Err(e)) => {
+                log::warn!("Invalid round in the header of batch {}: {}", digest, e);
+                self.round
+            }
+            None => self.round,
+        }
+    }
+
+    /// Reads a batch from the store
+    async fn read_batch(
+        &mut self,
+        hash: &BatchHash<Tx>,
+    ) -> Option<Batch<Tx>> {
+        match self.storage.get_raw(hash).await {
+            Ok(Some(data)) => match BatchStore::<Storage, Tx>::decode(&data) {
+                Ok(batch) => Some(batch),
+                Err(e) => {
+                    log::error!("Stored batch {} is corrupted: {}", hash, e);
+                    None
+                }
+            },
+            Ok(None) => {
+                log::debug!("Batch {} was already garbage collected", hash);
+                None
+            }
+            Err(e) => {
+                log::warn!("Store Error: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Moves the gc round up to `gc_depth` rounds before the current round,
+    /// and forgets about everything older. Returns false if the gc round did
+    /// not move.
+    ///
+    /// A gc depth of `Round::MIN` (the depth of unvalidated configs) disables
+    /// gc, rather than deleting every batch a round after it is stored.
+    fn advance_gc_round(&mut self) -> bool {
+        if self.gc_depth == Round::MIN {
+            log::debug!("Garbage collection is disabled");
+            return false;
+        }
+        // Rounds earlier than the gc depth have nothing to clean
+        let gc_round = self.round.saturating_sub(self.gc_depth);
+        if gc_round <= self.latest_gc_round {
+            log::debug!("Already cleaned {:?}", gc_round);
+            return false;
+        }
+
+        self.latest_gc_round = gc_round;
+        let mut cancelled = Vec::new();
+        for (hash, batch) in &self.pending {
+            if batch.round < gc_round {
+                batch.span.in_scope(|| tracing::debug!("Garbage collected before it arrived"));
+                let _ = batch.cancel.send(());
+                cancelled.push(hash.clone());
+            }
+        }
+        for hash in &cancelled {
+            self.fail_sync_requests(hash, "was garbage collected");
+        }
+        self.pending.retain(|_, batch| batch.round >= gc_round);
+        self.committed.retain(|_, r| *r >= gc_round);
+        self.uncommitted.retain(|_, r| *r >= gc_round);
+        self.committed_txs.retain(|_, r| *r >= gc_round);
+        true
+    }
+
+    /// Deletes all the batches (that are not pinned) older than the latest gc
+    /// round from the store, and returns their number
+    async fn cleanup(&mut self) -> usize {
+        let mut expired = Vec::new();
+        self.batch_rounds.retain(|hash, r| {
+            if *r < self.latest_gc_round && !self.pinned.contains(hash) {
+                expired.push(hash.clone());
+                return false;
+            }
+            true
+        });
+
+        let collected = expired.len();
+        for hash in expired {
+            log::debug!("Garbage collecting batch {}", hash);
+            self.storage.delete(&hash).await;
+            if let Some(index) = &mut self.index {
+                if let Err(e) = index.remove(&hash).await {
+                    log::warn!("Failed to unindex batch {}: {}", hash, e);
+                }
+            }
+            self.metrics.batches_collected.inc();
+        }
+        collected
+    }
 }
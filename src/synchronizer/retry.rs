@@ -0,0 +1,231 @@
+use super::PeerHealth;
+use fnv::{FnvHashMap, FnvHashSet};
+use rand::{seq::SliceRandom, Rng};
+use std::{cmp::Ordering, time::Duration};
+use tokio::{sync::mpsc::UnboundedSender, time::Instant};
+use tracing::Span;
+
+/// What we observed about a peer while synchronizing batches from it
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct PeerStats {
+    /// The number of batches that arrived after asking this peer
+    successes: u64,
+    /// The number of requests that this peer did not answer in time
+    failures: u64,
+    /// The (moving average of the) time it took to get a batch
+    latency: Option<Duration>,
+}
+
+impl PeerStats {
+    pub(crate) fn success(
+        &mut self,
+        latency: Duration,
+    ) {
+        self.successes += 1;
+        self.latency = Some(match self.latency {
+            Some(avg) => (avg * 7 + latency) / 8,
+            None => latency,
+        });
+    }
+
+    pub(crate) fn failure(&mut self) {
+        self.failures += 1;
+    }
+
+    /// The (smoothed) fraction of requests answered by this peer
+    pub(crate) fn success_rate(&self) -> f64 {
+        (self.successes + 1) as f64 / (self.successes + self.failures + 2) as f64
+    }
+
+    /// Reports these stats, for `peer`
+    pub(crate) fn health(
+        &self,
+        peer: String,
+    ) -> PeerHealth {
+        PeerHealth {
+            peer,
+            successes: self.successes,
+            failures: self.failures,
+            success_rate: self.success_rate(),
+            latency_ms: self.latency.map(|latency| latency.as_secs_f64() * 1_000.0),
+        }
+    }
+
+    /// Orders the better peers first: higher success rate, then lower latency
+    fn compare(
+        &self,
+        other: &Self,
+    ) -> Ordering {
+        other
+            .success_rate()
+            .partial_cmp(&self.success_rate())
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| match (self.latency, other.latency) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            })
+    }
+}
+
+/// Orders the better peers first, the peers we know nothing about come last
+fn compare_peers<Id>(
+    stats: &FnvHashMap<Id, PeerStats>,
+    a: &Id,
+    b: &Id,
+) -> Ordering
+where
+    Id: Eq + std::hash::Hash,
+{
+    match (stats.get(a), stats.get(b)) {
+        (Some(a), Some(b)) => a.compare(b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// The synchronization state of a batch that we are waiting for
+pub(crate) struct PendingBatch<Id, Round> {
+    /// The round in which the batch was requested
+    pub(crate) round: Round,
+    /// Used to cancel the waiter of this batch
+    pub(crate) cancel: UnboundedSender<()>,
+    /// The number of times we retried to sync this batch
+    pub(crate) attempts: usize,
+    /// When to retry next
+    pub(crate) next_retry: Instant,
+    /// The peers that likely hold this batch
+    holders: Vec<Id>,
+    /// The peers that we already asked for this batch
+    tried: FnvHashSet<Id>,
+    /// The peers we asked in the latest attempt, and when
+    last_asked: Vec<(Id, Instant)>,
+    /// Covers the synchronization of this batch, from the request to its
+    /// resolution
+    pub(crate) span: Span,
+}
+
+impl<Id, Round> PendingBatch<Id, Round>
+where
+    Id: Clone + Eq + std::hash::Hash,
+{
+    pub(crate) fn new(
+        round: Round,
+        cancel: UnboundedSender<()>,
+        source: Id,
+        holders: Vec<Id>,
+        retry_delay: Duration,
+    ) -> Self {
+        let now = Instant::now();
+        let mut tried = FnvHashSet::default();
+        tried.insert(source.clone());
+        Self {
+            round,
+            cancel,
+            attempts: 0,
+            next_retry: now + retry_delay,
+            holders,
+            tried,
+            last_asked: vec![(source, now)],
+            span: Span::none(),
+        }
+    }
+
+    /// Records the synchronization of this batch in `span`
+    pub(crate) fn with_span(
+        mut self,
+        span: Span,
+    ) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// Remembers more peers that likely hold this batch
+    pub(crate) fn add_holders(
+        &mut self,
+        holders: &[Id],
+    ) {
+        for holder in holders {
+            if !self.holders.contains(holder) {
+                self.holders.push(holder.clone());
+            }
+        }
+    }
+
+    /// Schedules the next retry, `retry_delay` after the first one and doubling
+    /// the delay on every attempt after that (up to `max_delay`), with up to
+    /// 50% of random jitter. The peers asked in the previous attempt failed to
+    /// answer in time.
+    pub(crate) fn backoff(
+        &mut self,
+        retry_delay: Duration,
+        max_delay: Duration,
+        stats: &mut FnvHashMap<Id, PeerStats>,
+    ) {
+        for (peer, _) in self.last_asked.drain(..) {
+            stats.entry(peer).or_default().failure();
+        }
+
+        let exp = self.attempts.min(16) as u32;
+        self.attempts += 1;
+        let delay = retry_delay.saturating_mul(1 << exp).min(max_delay);
+        let jitter = rand::thread_rng().gen_range(0..=delay.as_millis() as u64 / 2);
+        self.next_retry = Instant::now() + delay + Duration::from_millis(jitter);
+    }
+
+    /// The batch arrived, credit the peers asked in the latest attempt
+    pub(crate) fn delivered(
+        &mut self,
+        stats: &mut FnvHashMap<Id, PeerStats>,
+    ) {
+        let now = Instant::now();
+        for (peer, asked_at) in self.last_asked.drain(..) {
+            stats.entry(peer).or_default().success(now - asked_at);
+        }
+    }
+
+    /// Picks up to `num_peers` peers that we did not ask yet. The likely
+    /// holders come first, then the peers that served us best so far, and only
+    /// then random peers. Once every peer was asked, we start over.
+    pub(crate) fn select_peers(
+        &mut self,
+        all_ids: &[Id],
+        my_name: &Id,
+        num_peers: usize,
+        stats: &FnvHashMap<Id, PeerStats>,
+    ) -> Vec<Id> {
+        if all_ids
+            .iter()
+            .all(|id| id == my_name || self.tried.contains(id))
+        {
+            self.tried.clear();
+        }
+        let untried = |id: &&Id| *id != my_name && !self.tried.contains(*id);
+
+        let mut holders: Vec<Id> = self.holders.iter().filter(untried).cloned().collect();
+        holders.sort_by(|a, b| compare_peers(stats, a, b));
+
+        let others = all_ids
+            .iter()
+            .filter(untried)
+            .filter(|id| !self.holders.contains(*id));
+        let (mut known, mut unknown): (Vec<Id>, Vec<Id>) =
+            others.cloned().partition(|id| stats.contains_key(id));
+        known.sort_by(|a, b| compare_peers(stats, a, b));
+        unknown.shuffle(&mut rand::thread_rng());
+
+        let selected: Vec<Id> = holders
+            .into_iter()
+            .chain(known)
+            .chain(unknown)
+            .take(num_peers)
+            .collect();
+
+        let now = Instant::now();
+        self.tried.extend(selected.iter().cloned());
+        self.last_asked = selected.iter().map(|id| (id.clone(), now)).collect();
+        selected
+    }
+}
@@ -1,20 +1,23 @@
+use crate::{BatchHash, BatchStore};
 use anyhow::Result;
-use libcrypto::hash::Hash;
+use serde::{de::DeserializeOwned, Serialize};
 use tokio::sync::mpsc::UnboundedReceiver;
 
-/// This struct waits for a key to be available in a Store
-pub async fn wait<Storage, T>(
-    mut store: Storage,
-    key: Hash<T>,
+/// This struct waits for a batch to be available in a Store. Returns the
+/// digest, with whether the batch arrived (`false` if the wait was cancelled)
+/// or the error of the store.
+pub async fn wait<Storage, Tx>(
+    mut store: BatchStore<Storage, Tx>,
+    digest: BatchHash<Tx>,
     mut cancel_handler: UnboundedReceiver<()>,
-) -> Result<Option<Vec<u8>>>
+) -> (BatchHash<Tx>, Result<bool>)
 where
     Storage: libstorage::Store,
+    Tx: Serialize + DeserializeOwned,
 {
-    tokio::select! {
-        result = store.notify_read(key.to_vec()) => {
-            result.map(|_| Some(key.to_vec()))
-        },
-        _ = cancel_handler.recv() => Ok(None),
-    }
+    let result = tokio::select! {
+        result = store.notify_read(&digest) => result.map(|_| true),
+        _ = cancel_handler.recv() => Ok(false),
+    };
+    (digest, result)
 }
@@ -0,0 +1,125 @@
+use super::{Round, Tx};
+use crate::{
+    batcher::Batcher, sealer::Timed, serve_admin, Admin, Batch, BatchStore, Config, MemoryStore,
+    MempoolQuery, Metrics, SyncStatus, SynchronizerQuery,
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::{mpsc::unbounded_channel, watch},
+    time,
+};
+
+const ADMIN_PORT: u16 = 16_000;
+
+/// Talks to the admin endpoint
+struct AdminClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl AdminClient {
+    async fn connect(addr: std::net::SocketAddr) -> anyhow::Result<Self> {
+        let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+
+    async fn request(
+        &mut self,
+        request: Value,
+    ) -> anyhow::Result<Value> {
+        let line = format!("{}\n", request);
+        self.writer.write_all(line.as_bytes()).await?;
+        let response = self.lines.next_line().await?.unwrap();
+        Ok(serde_json::from_str(&response)?)
+    }
+}
+
+/// Check that the admin endpoint reports the state of the mempool, and drives
+/// the batcher and the synchronizer
+#[tokio::test]
+async fn test_admin() -> anyhow::Result<()> {
+    let (tx_batcher, rx_batcher) = unbounded_channel();
+    let (tx_output, mut rx_output) = unbounded_channel();
+    let batcher = Batcher::spawn(
+        rx_batcher,
+        tx_output,
+        Timed::new(Duration::from_secs(60)),
+        Metrics::default(),
+    );
+
+    // Play the synchronizer
+    let stuck = Batch::from(vec![Tx(false)]).digest();
+    let (tx_query, mut rx_query) = unbounded_channel();
+    let pending = stuck.clone();
+    tokio::spawn(async move {
+        while let Some(query) = rx_query.recv().await {
+            match query {
+                SynchronizerQuery::Pending(tx_reply) => {
+                    let _ = tx_reply.send(vec![pending.clone()]);
+                }
+                SynchronizerQuery::Status(tx_reply) => {
+                    let _ = tx_reply.send(SyncStatus {
+                        round: "3".to_string(),
+                        gc_round: "1".to_string(),
+                        pending: 1,
+                        sync_requests: 1,
+                        stored: 0,
+                        pinned: 0,
+                        uncommitted: 0,
+                    });
+                }
+                SynchronizerQuery::Drop(hash, tx_reply) => {
+                    let _ = tx_reply.send(hash == pending);
+                }
+                _ => panic!("Unexpected query"),
+            }
+        }
+    });
+
+    let (_tx_report, rx_report) = watch::channel(None);
+    let query = MempoolQuery::<MemoryStore, Tx>::new(
+        BatchStore::new(MemoryStore::new()),
+        tx_query,
+        rx_report,
+    );
+    let (_tx_config, rx_config) = watch::channel(Config::<Round>::default());
+    let admin = Admin::new(query, Some(batcher), rx_config);
+    let addr = format!("127.0.0.1:{}", ADMIN_PORT).parse()?;
+    serve_admin(admin, addr).await?;
+
+    tx_batcher.send((Tx(true), 1))?;
+    tx_batcher.send((Tx(true), 1))?;
+    time::sleep(Duration::from_millis(50)).await;
+
+    let mut client = AdminClient::connect(addr).await?;
+    let status = client.request(json!({ "cmd": "status" })).await?;
+    assert_eq!(status["result"]["sealer_pending"], json!(2));
+    assert_eq!(status["result"]["sync"]["gc_round"], json!("1"));
+
+    let sealed = client.request(json!({ "cmd": "seal" })).await?;
+    assert_eq!(sealed["result"]["sealed"], json!(2));
+    assert_eq!(rx_output.recv().await.unwrap().payload, vec![Tx(true), Tx(true)]);
+
+    let pending = client.request(json!({ "cmd": "pending_sync" })).await?;
+    assert_eq!(pending["result"], json!([stuck.to_string()]));
+    let dropped = client
+        .request(json!({ "cmd": "drop_sync", "digest": stuck.to_string() }))
+        .await?;
+    assert_eq!(dropped["result"]["dropped"], json!(true));
+
+    let config = client.request(json!({ "cmd": "config" })).await?;
+    assert_eq!(config["result"]["sync_retry_nodes"], json!(3));
+
+    let invalid = client.request(json!({ "cmd": "reboot" })).await?;
+    assert_eq!(invalid["ok"], json!(false));
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use super::Tx;
+use crate::{tx_hash, Batch, BatchIndex, BatchStore, MemoryStore};
+use libstorage::Store;
+
+/// Check that batches round-trip, and that deleting a batch also removes the
+/// index of its transactions and its metadata
+#[tokio::test]
+async fn test_batch_store() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+
+    let batch = Batch::from(vec![Tx(true)]);
+    let digest = store.put(&batch).await;
+    store.put_meta(&digest, &42u64).await;
+    assert!(store.exists(&digest).await?);
+    assert_eq!(store.get(&digest).await?, Some(batch));
+    assert_eq!(store.batch_of(&tx_hash(&Tx(true))).await?, Some(digest.clone()));
+    assert_eq!(store.get_meta::<u64>(&digest).await?, Some(42));
+    assert_eq!(store.notify_read(&digest).await?, store.get_raw(&digest).await?.unwrap());
+
+    store.delete(&digest).await;
+    assert!(!store.exists(&digest).await?);
+    assert_eq!(store.batch_of(&tx_hash(&Tx(true))).await?, None);
+    assert_eq!(store.get_meta::<u64>(&digest).await?, None);
+    Ok(())
+}
+
+/// Check that the index lists every indexed batch once, until it is removed,
+/// and that it is picked up again after a restart
+#[tokio::test]
+async fn test_index() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let mut index = BatchIndex::load(store.clone()).await?;
+    assert!(store.scan().await?.is_empty());
+
+    let first = store.put(&Batch::from(vec![Tx(true)])).await;
+    let second = store.put(&Batch::from(vec![Tx(false)])).await;
+    let third = store.put(&Batch::from(vec![Tx(true), Tx(false)])).await;
+    index.add(&first).await?;
+    index.add(&second).await?;
+    index.add(&first).await?;
+    index.add(&third).await?;
+    assert_eq!(store.scan().await?, vec![first.clone(), second.clone(), third.clone()]);
+
+    index.remove(&second).await?;
+    assert_eq!(store.scan().await?, vec![first.clone(), third.clone()]);
+
+    // The hole left by the second batch is skipped along with the first
+    let mut index = BatchIndex::load(store.clone()).await?;
+    index.remove(&first).await?;
+    assert_eq!(store.scan().await?, vec![third.clone()]);
+    index.add(&second).await?;
+    assert_eq!(store.scan().await?, vec![third, second]);
+    Ok(())
+}
+
+/// Check that batches stored before namespaces were introduced are still
+/// found, without being rewritten, and that deleting them removes their keys
+#[tokio::test]
+async fn test_legacy_keys() -> anyhow::Result<()> {
+    let mut raw = MemoryStore::new();
+    let mut store = BatchStore::<MemoryStore, Tx>::new(raw.clone());
+
+    let batch = Batch::from(vec![Tx(true)]);
+    let data = BatchStore::<MemoryStore, Tx>::encode(&batch);
+    let digest = BatchStore::<MemoryStore, Tx>::digest(&data);
+    let tx_key = [&b"tx"[..], &tx_hash(&Tx(true)).to_vec()].concat();
+    raw.write(digest.to_vec(), data).await;
+    raw.write(tx_key.clone(), digest.to_vec()).await;
+
+    assert_eq!(store.get(&digest).await?, Some(batch));
+    assert_eq!(store.batch_of(&tx_hash(&Tx(true))).await?, Some(digest.clone()));
+    assert!(store.exists(&digest).await?);
+    assert!(raw.read(digest.to_vec()).await?.is_some());
+    assert!(raw.read(tx_key.clone()).await?.is_some());
+
+    store.delete(&digest).await;
+    assert!(!store.exists(&digest).await?);
+    assert_eq!(raw.read(digest.to_vec()).await?, None);
+    assert_eq!(raw.read(tx_key).await?, None);
+    Ok(())
+}
+
+/// Check that deleting a batch that no longer decodes still removes the index
+/// of its transactions
+#[tokio::test]
+async fn test_delete_corrupted() -> anyhow::Result<()> {
+    let mut store = BatchStore::<MemoryStore, Tx>::new(MemoryStore::new());
+    let digest = store.put(&Batch::from(vec![Tx(true)])).await;
+    store.put_raw(&digest, vec![0xff; 3]).await;
+    assert!(store.get(&digest).await.is_err());
+
+    store.delete(&digest).await;
+    assert!(!store.exists(&digest).await?);
+    assert_eq!(store.batch_of(&tx_hash(&Tx(true))).await?, None);
+    Ok(())
+}
+
+/// Check that deleting a batch leaves alone the transactions since indexed
+/// under another batch
+#[tokio::test]
+async fn test_delete_reindexed() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+
+    let first = store.put(&Batch::from(vec![Tx(true)])).await;
+    let second = store.put(&Batch::from(vec![Tx(true), Tx(false)])).await;
+    assert_eq!(store.batch_of(&tx_hash(&Tx(true))).await?, Some(second.clone()));
+
+    store.delete(&first).await;
+    assert_eq!(store.batch_of(&tx_hash(&Tx(true))).await?, Some(second));
+    Ok(())
+}
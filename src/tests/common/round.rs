@@ -31,6 +31,8 @@ impl From<usize> for Round {
     }
 }
 
+// Relies on the default `checked_sub`, as most implementations would
 impl crate::Round for Round {
     const MIN: Self = Self(0);
+    const DEFAULT_GC_DEPTH: Self = Self(50);
 }
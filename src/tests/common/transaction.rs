@@ -14,3 +14,6 @@ impl net_common::Message for Tx {
 pub fn dummy_tx() -> Tx {
     Tx(true)
 }
+
+#[cfg(feature = "node")]
+impl crate::Sampled for Tx {}
@@ -0,0 +1,115 @@
+use super::{Round, Tx};
+use crate::{batcher::Batcher, sealer::Sized, Config, ConfigUpdater, Metrics, SealerParams};
+use std::time::Duration;
+use tokio::{sync::mpsc::unbounded_channel, time};
+
+/// A valid config
+fn valid_config() -> Config<Round> {
+    Config {
+        gc_depth: 10.into(),
+        ..Default::default()
+    }
+}
+
+/// Check that updates are validated, that fixed parameters are rejected, and
+/// that the subscribers see the rest
+#[tokio::test]
+async fn test_config_update() -> anyhow::Result<()> {
+    let config = ConfigUpdater::new(valid_config())?;
+    let mut rx_config = config.subscribe();
+
+    let invalid = Config {
+        sync_retry_nodes: 0,
+        ..config.current()
+    };
+    assert!(config.update(invalid).is_err(), "Invalid config was accepted");
+    assert!(ConfigUpdater::new(Config::<Round>::default()).is_ok(), "Default config was rejected");
+    assert!(Config::<u64>::default().validate().is_ok(), "Default config was rejected");
+    let unset = Config {
+        gc_depth: 0.into(),
+        ..config.current()
+    };
+    assert!(config.update(unset).is_err(), "Config without a gc depth was accepted");
+    let eager = Config {
+        reinject_depth: Some(0.into()),
+        ..config.current()
+    };
+    assert!(config.update(eager).is_err(), "Re-inject depth of 0 was accepted");
+    let exposed = Config {
+        metrics_addr: Some("0.0.0.0:9100".parse()?),
+        ..config.current()
+    };
+    assert!(config.update(exposed).is_err(), "Public metrics address was accepted");
+    let oversized = Config {
+        max_batch_size: config.current().sync_max_bytes_per_request + 1,
+        ..config.current()
+    };
+    assert!(config.update(oversized).is_err(), "Unservable batch size was accepted");
+
+    let fixed = Config {
+        sync_helper_workers: 1,
+        ..config.current()
+    };
+    assert!(config.update(fixed).is_err(), "Fixed parameter was changed");
+    assert!(!rx_config.has_changed()?, "Rejected updates were sent");
+
+    assert_eq!(config.update(config.current())?, 0);
+    assert!(!rx_config.has_changed()?, "Empty update was sent");
+
+    let tuned = Config {
+        gc_depth: 5.into(),
+        sync_retry_delay: Duration::from_millis(50),
+        ..config.current()
+    };
+    assert_eq!(config.update(tuned.clone())?, 2);
+    rx_config.changed().await?;
+    assert_eq!(*rx_config.borrow(), tuned);
+    Ok(())
+}
+
+/// Check that durations are read in ms, as well as in the form of the configs
+/// serialized before
+#[test]
+fn test_legacy_durations() -> anyhow::Result<()> {
+    let config: Config<Round> = serde_json::from_str(
+        r#"{
+            "sync_retry_delay": 200,
+            "sync_retry_max_delay": { "secs": 2, "nanos": 500000000 },
+            "scrub_interval": { "secs": 60, "nanos": 0 }
+        }"#,
+    )?;
+    assert_eq!(config.sync_retry_delay, Duration::from_millis(200));
+    assert_eq!(config.sync_retry_max_delay, Duration::from_millis(2_500));
+    assert_eq!(config.scrub_interval, Some(Duration::from_secs(60)));
+
+    let json = serde_json::to_value(&config)?;
+    assert_eq!(json["sync_retry_max_delay"], 2_500);
+    Ok(())
+}
+
+/// Check that a running batcher follows the sealer parameters of the config
+#[tokio::test]
+async fn test_sealer_reload() -> anyhow::Result<()> {
+    let (tx_batcher, rx_batcher) = unbounded_channel();
+    let (tx_output, mut rx_output) = unbounded_channel();
+    let config = ConfigUpdater::new(valid_config())?;
+    let sealer = Sized::new(config.current().sealer.size);
+    let batcher = Batcher::spawn(rx_batcher, tx_output, sealer, Metrics::default());
+    batcher.follow(config.subscribe());
+
+    tx_batcher.send((Tx(true), 1))?;
+    tx_batcher.send((Tx(false), 1))?;
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(rx_output.try_recv().is_err(), "Sealed before the update");
+
+    config.update(Config {
+        sealer: SealerParams {
+            size: 2,
+            ..SealerParams::default()
+        },
+        ..config.current()
+    })?;
+    let batch = time::timeout(Duration::from_secs(1), rx_output.recv()).await?;
+    assert_eq!(batch.unwrap().payload, vec![Tx(true), Tx(false)]);
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use super::{get_peers, Id, Tx};
+use crate::{
+    Batch, BatchStore, Helper, HelperRequest, MemoryStore, MempoolMsg, Metrics, SimConfig,
+    SimNetwork,
+};
+use futures::StreamExt;
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tcp_sender::TcpSimpleSender;
+use tokio::{sync::mpsc::unbounded_channel, time};
+
+const HELPER_BASE_PORT: u16 = 14_000;
+
+/// Check that a corrupted batch is not served, but deleted and re-synced
+#[tokio::test]
+async fn test_corrupted_batch() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let (tx_request, rx_request) = unbounded_channel();
+    let (tx_resync, mut rx_resync) = unbounded_channel();
+    Helper::<Id, MemoryStore, Tx>::spawn(
+        0,
+        TcpSimpleSender::with_peers(get_peers(2, HELPER_BASE_PORT)),
+        rx_request,
+        store.clone(),
+        1 << 20,
+        4,
+        16 << 20,
+        2,
+        tx_resync,
+        Metrics::default(),
+    );
+
+    let mut data = BatchStore::<MemoryStore, Tx>::encode(&Batch::from(vec![Tx(true)]));
+    let digest = BatchStore::<MemoryStore, Tx>::digest(&data);
+    // Flip a bit
+    let last = data.len() - 1;
+    data[last] ^= 1;
+    store.put_raw(&digest, data).await;
+
+    tx_request.send(HelperRequest::Batches(1, vec![digest.clone()]))?;
+    let resync = time::timeout(Duration::from_secs(1), rx_resync.recv()).await?;
+    assert_eq!(resync, Some(digest.clone()));
+    assert!(!store.exists(&digest).await?, "Corrupted batch was not deleted");
+    Ok(())
+}
+
+/// Check that the byte budget of a streamed batch covers its resumes
+#[tokio::test(start_paused = true)]
+async fn test_stream_budget() -> anyhow::Result<()> {
+    let network = SimNetwork::new(SimConfig::default());
+    let requester = SocketAddr::new(IpAddr::from([10, 0, 0, 1]), HELPER_BASE_PORT);
+    let peers = [(1, requester)].into_iter().collect();
+    let mut receiver = network.receiver::<MempoolMsg<Id, Tx>>(requester);
+
+    let mut store = BatchStore::new(MemoryStore::new());
+    let (tx_request, rx_request) = unbounded_channel();
+    let (tx_resync, _rx_resync) = unbounded_channel();
+    // Chunks of 4 bytes, one per window, and 8 bytes per request
+    Helper::<Id, MemoryStore, Tx, _>::spawn(
+        0,
+        network.sender::<Id, MempoolMsg<Id, Tx>>(IpAddr::from([10, 0, 0, 0]), peers),
+        rx_request,
+        store.clone(),
+        4,
+        1,
+        8,
+        1,
+        tx_resync,
+        Metrics::default(),
+    );
+    let digest = store.put(&Batch::from(vec![Tx(true); 16])).await;
+
+    let mut offsets = Vec::new();
+    tx_request.send(HelperRequest::Batches(1, vec![digest.clone()]))?;
+    for offset in [4, 8] {
+        match time::timeout(Duration::from_secs(1), receiver.next()).await {
+            Ok(Some(Ok(MempoolMsg::BatchChunk(_, chunk)))) => offsets.push(chunk.offset),
+            _ => break,
+        }
+        tx_request.send(HelperRequest::Chunks(1, digest.clone(), offset))?;
+    }
+    assert_eq!(offsets, vec![0, 4]);
+    let more = time::timeout(Duration::from_secs(1), receiver.next()).await;
+    assert!(more.is_err(), "Sent more than the budget of the request");
+    Ok(())
+}
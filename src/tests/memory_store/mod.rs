@@ -0,0 +1,50 @@
+use crate::MemoryStore;
+use libstorage::Store;
+use std::time::Duration;
+use tokio::time;
+
+/// Check that clones share their contents, and that `notify_read` resolves
+/// once the key is written
+#[tokio::test]
+async fn test_memory_store() -> anyhow::Result<()> {
+    let mut store = MemoryStore::new();
+    let mut reader = store.clone();
+    let pending = tokio::spawn(async move { reader.notify_read(b"key".to_vec()).await });
+
+    time::sleep(Duration::from_millis(10)).await;
+    assert!(!pending.is_finished(), "Read resolved before the write");
+    store.write(b"key".to_vec(), b"value".to_vec()).await;
+    let value = time::timeout(Duration::from_secs(1), pending).await???;
+    assert_eq!(value, b"value".to_vec());
+
+    // Reads of existing keys resolve immediately
+    assert_eq!(store.clone().notify_read(b"key".to_vec()).await?, b"value".to_vec());
+
+    store.delete(b"key".to_vec()).await;
+    assert_eq!(store.read(b"key".to_vec()).await?, None);
+    assert!(store.is_empty());
+    Ok(())
+}
+
+/// Check that cancelled reads of keys that are never written are dropped
+#[tokio::test]
+async fn test_cancelled_reads() -> anyhow::Result<()> {
+    let mut store = MemoryStore::new();
+    for i in 0..10u8 {
+        let mut reader = store.clone();
+        let read = reader.notify_read(vec![i]);
+        assert!(time::timeout(Duration::from_millis(1), read).await.is_err());
+    }
+    assert_eq!(store.waiting(), 10);
+
+    // The next read prunes the cancelled ones
+    let mut reader = store.clone();
+    let pending = tokio::spawn(async move { reader.notify_read(b"key".to_vec()).await });
+    time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(store.waiting(), 1);
+
+    store.write(b"key".to_vec(), b"value".to_vec()).await;
+    assert_eq!(pending.await??, b"value".to_vec());
+    assert_eq!(store.waiting(), 0);
+    Ok(())
+}
@@ -1,39 +1,133 @@
 use super::{get_peers, Id, Round, Tx};
 use crate::batcher::Batcher;
 use crate::Batch;
-use crate::{sealer::Sized, Config, Mempool, MempoolMsg};
+use crate::{
+    sealer::Sized, Config, ConfigUpdater, MemoryStore, Mempool, MempoolMsg, Metrics, SimConfig,
+    SimNetwork,
+};
 use bytes::Bytes;
+use fnv::FnvHashMap;
 use libcrypto::hash::Hash;
-use libstorage::rocksdb::Storage;
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
 use tcp_sender::TcpSimpleSender;
-use std::time::Duration;
 use tokio::{
     sync::mpsc::{unbounded_channel, UnboundedReceiver},
     time::{self, Instant},
 };
 
-const CLIENT_BASE_PORT: u16 = 9_000;
-const MEMPOOL_BASE_PORT: u16 = 10_000;
+const CLIENT_PORT: u16 = 9_000;
+const MEMPOOL_PORT: u16 = 10_000;
+const NUM_NODES: [usize; 6] = [4, 9, 16, 31, 67, 129];
 
-#[tokio::test]
+/// The host of node `i`, or of the client past the last node
+fn host(i: usize) -> IpAddr {
+    IpAddr::from([10, 0, (i >> 8) as u8, i as u8])
+}
+
+#[tokio::test(start_paused = true)]
 async fn test_mempool() -> anyhow::Result<()> {
-    let num_nodes = vec![4, 9, 16, 31, 67, 129];
+    for num_nodes in NUM_NODES {
+        do_test_mempool(num_nodes).await?;
+    }
+    Ok(())
+}
+
+/// The same over real TCP connections, which benchmarks the mempool. Ignored
+/// by default, as it binds hundreds of ports, run it with `--ignored`.
+#[tokio::test]
+#[ignore]
+async fn test_mempool_tcp() -> anyhow::Result<()> {
     let mut ports_so_far: u16 = 0;
-    for num_node in num_nodes {
-        do_test_mempool(
-            num_node,
-            CLIENT_BASE_PORT + ports_so_far,
-            MEMPOOL_BASE_PORT + ports_so_far,
-        )
-        .await?;
+    for num_nodes in NUM_NODES {
+        do_test_mempool_tcp(num_nodes, CLIENT_PORT + ports_so_far, MEMPOOL_PORT + ports_so_far)
+            .await?;
         time::sleep(Duration::from_millis(1_000)).await;
+        ports_so_far += num_nodes as u16;
+    }
+    Ok(())
+}
+
+async fn do_test_mempool(num_nodes: usize) -> anyhow::Result<()> {
+    let network = SimNetwork::new(SimConfig::default());
+    let peers = |port| -> FnvHashMap<Id, SocketAddr> {
+        (0..num_nodes)
+            .map(|i| (i as Id, SocketAddr::new(host(i), port)))
+            .collect()
+    };
+    let (mempool_peers, client_peers) = (peers(MEMPOOL_PORT), peers(CLIENT_PORT));
+    let all_ids: Vec<Id> = mempool_peers.keys().cloned().collect();
+
+    let config = ConfigUpdater::new(Config::<Round> {
+        gc_depth: 2.into(),
+        ..Default::default()
+    })?;
+
+    let mut receivers = Vec::<UnboundedReceiver<Hash<Batch<Tx>>>>::new();
+
+    for i in 0..num_nodes {
+        let my_name: Id = i;
+        let store = MemoryStore::new();
+        let mempool_sender =
+            network.sender::<Id, MempoolMsg<Id, Tx>>(host(i), mempool_peers.clone());
+
+        // tx_consensus is to be used inside consensus for synchronization or garbage
+        // collection
+        let (_tx_consensus, rx_consensus) = unbounded_channel();
+        let (tx_batcher, rx_batcher) = unbounded_channel();
+        let (tx_processor, rx_processor) = unbounded_channel();
+        let (tx_in_consensus, rx_in_consensus) = unbounded_channel();
+
+        let metrics = Metrics::default();
+        Batcher::spawn(rx_batcher, tx_processor.clone(), Sized::new(2), metrics.clone());
 
-        ports_so_far += num_node as u16;
+        Mempool::spawn(
+            my_name,
+            all_ids.clone(),
+            config.subscribe(),
+            metrics,
+            store.clone(),
+            mempool_sender,
+            rx_consensus,
+            tx_batcher,
+            tx_processor,
+            rx_processor,
+            tx_in_consensus,
+            mempool_peers[&my_name],
+            client_peers[&my_name],
+        );
+
+        receivers.push(rx_in_consensus);
     }
+
+    let mut client_sender = network.sender::<Id, Tx>(host(num_nodes), client_peers);
+    let test_tx = crate::tests::dummy_tx();
+
+    let start = Instant::now();
+
+    for i in 0..num_nodes {
+        let serialized = Bytes::from(bincode::serialize(&test_tx).unwrap());
+        for _ in 0..4 {
+            client_sender.send(i, serialized.clone()).await?;
+        }
+    }
+
+    for mut receiver in receivers {
+        let processed_hash = receiver.recv().await;
+        assert!(processed_hash.is_some(), "Got empty processed_hash");
+        let _ = processed_hash.unwrap();
+        // TODO: Check with expected processed_hash
+    }
+    // In simulated time, i.e., the delays of the network. Not a benchmark.
+    let sim_time = (Instant::now() - start).as_millis();
+    println!("Simulated time with {} nodes: {} ms", num_nodes, sim_time);
+
     Ok(())
 }
 
-async fn do_test_mempool(
+async fn do_test_mempool_tcp(
     num_nodes: usize,
     client_base_port: u16,
     mempool_base_port: u16,
@@ -44,50 +138,42 @@ async fn do_test_mempool(
     );
     let all_ids: Vec<Id> = mempool_peers.keys().cloned().collect();
 
-    let params = Config::<Round> {
+    let config = ConfigUpdater::new(Config::<Round> {
         gc_depth: 2.into(),
         ..Default::default()
-    };
+    })?;
 
     let mut receivers = Vec::<UnboundedReceiver<Hash<Batch<Tx>>>>::new();
+    let mut latencies = Vec::new();
 
     for i in 0..num_nodes {
         let my_name: Id = i;
-        let store = Storage::new(format!(".mempool_tests{}-{}.db", num_nodes, i).as_str())?;
-        let mempool_sender = TcpSimpleSender::<Id, MempoolMsg<Id, Tx>>::with_peers(
-            mempool_peers.clone(),
-        );
+        let mempool_sender =
+            TcpSimpleSender::<Id, MempoolMsg<Id, Tx>>::with_peers(mempool_peers.clone());
 
-        // tx_consensus is to be used inside consensus for synchronization or garbage
-        // collection
         let (_tx_consensus, rx_consensus) = unbounded_channel();
         let (tx_batcher, rx_batcher) = unbounded_channel();
         let (tx_processor, rx_processor) = unbounded_channel();
         let (tx_in_consensus, rx_in_consensus) = unbounded_channel();
 
-        Batcher::spawn(rx_batcher, tx_processor.clone(), Sized::new(2));
-
-        let (my_mempool_addr, my_client_addr) = {
-            let mut mempool_addr = mempool_peers[&my_name];
-            let mut client_addr = client_peers[&my_name];
-            mempool_addr.set_ip("0.0.0.0".parse()?);
-            client_addr.set_ip("0.0.0.0".parse()?);
-            (mempool_addr, client_addr)
-        };
+        let metrics = Metrics::default().with_latency_sampling(1);
+        latencies.push(metrics.latency.clone());
+        Batcher::spawn(rx_batcher, tx_processor.clone(), Sized::new(2), metrics.clone());
 
         Mempool::spawn(
             my_name,
             all_ids.clone(),
-            params.clone(),
-            store.clone(),
+            config.subscribe(),
+            metrics,
+            MemoryStore::new(),
             mempool_sender,
             rx_consensus,
             tx_batcher,
             tx_processor,
             rx_processor,
             tx_in_consensus,
-            my_mempool_addr,
-            my_client_addr,
+            mempool_peers[&my_name],
+            client_peers[&my_name],
         );
 
         receivers.push(rx_in_consensus);
@@ -100,20 +186,17 @@ async fn do_test_mempool(
 
     for i in 0..num_nodes {
         let serialized = Bytes::from(bincode::serialize(&test_tx).unwrap());
-        let _ = client_sender.send(i, serialized.clone()).await;
-        let _ = client_sender.send(i, serialized.clone()).await;
-        let _ = client_sender.send(i, serialized.clone()).await;
-        let _ = client_sender.send(i, serialized.clone()).await;
+        for _ in 0..4 {
+            let _ = client_sender.send(i, serialized.clone()).await;
+        }
     }
 
     for mut receiver in receivers {
-        let processed_hash = receiver.recv().await;
-        assert!(processed_hash.is_some(), "Got empty processed_hash");
-        let _ = processed_hash.unwrap();
-        // TODO: Check with expected processed_hash
+        assert!(receiver.recv().await.is_some(), "Got empty processed_hash");
     }
-    let bench_time = (Instant::now() - start).as_micros();
+    let bench_time = (Instant::now() - start).as_millis();
     println!("Bench {}: {} ms", num_nodes, bench_time);
+    print!("{}", latencies[0].report());
 
     Ok(())
 }
@@ -0,0 +1,84 @@
+use super::Tx;
+use crate::{serve_metrics, Batch, Metrics, Registry};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const METRICS_PORT: u16 = 15_000;
+
+/// Check that metrics registered twice are shared, and that they are rendered
+/// in the Prometheus text format
+#[test]
+fn test_render() {
+    let registry = Registry::new();
+    let metrics = Metrics::new(&registry);
+    let other = Metrics::new(&registry);
+    metrics.transactions_received.inc_by(3);
+    other.transactions_received.inc();
+    metrics.batches_sealed("sized").inc();
+    other.sync_pending.set(2);
+    metrics.sync_attempts.observe_value(2.0);
+
+    assert_eq!(metrics.transactions_received.get(), 4);
+    let text = registry.render();
+    assert!(text.contains("# TYPE mempool_transactions_received_total counter\n"));
+    assert!(text.contains("mempool_transactions_received_total 4\n"));
+    assert!(text.contains("mempool_batches_sealed_total{sealer=\"sized\"} 1\n"));
+    assert!(text.contains("mempool_sync_pending 2\n"));
+    assert!(text.contains("mempool_sync_attempts_bucket{le=\"1\"} 0\n"));
+    assert!(text.contains("mempool_sync_attempts_bucket{le=\"2\"} 1\n"));
+    assert!(text.contains("mempool_sync_attempts_sum 2\n"));
+
+    metrics.batches_sealed("a\\b\"c\nd").inc();
+    let text = registry.render();
+    assert!(text.contains("mempool_batches_sealed_total{sealer=\"a\\\\b\\\"c\\nd\"} 1\n"));
+}
+
+/// Check that sampled transactions are followed through every stage
+#[test]
+fn test_latency() {
+    let registry = Registry::new();
+    let metrics = Metrics::new(&registry).with_latency_sampling(1);
+    let batch = Batch::from(vec![Tx(true)]);
+    let digest = batch.digest();
+
+    metrics.latency.submitted(&Tx(true));
+    metrics.latency.sealed(&batch);
+    metrics.latency.stored(&digest);
+    metrics.latency.committed(&digest);
+
+    let report = metrics.latency.report();
+    assert_eq!(report.client_to_batch.unwrap().samples, 1);
+    assert_eq!(report.batch_to_stored.unwrap().samples, 1);
+    assert_eq!(report.stored_to_consensus.unwrap().samples, 1);
+    assert!(report.to_string().starts_with("Bench client_to_batch: "));
+    assert!(registry
+        .render()
+        .contains("mempool_stored_to_consensus_seconds_bucket{le=\"+Inf\"} 1\n"));
+}
+
+/// Check that the metrics are served over HTTP
+#[tokio::test]
+async fn test_serve_metrics() -> anyhow::Result<()> {
+    let registry = Registry::new();
+    Metrics::new(&registry).batches_stored.inc();
+    let addr = format!("127.0.0.1:{}", METRICS_PORT).parse()?;
+    serve_metrics(registry, addr).await?;
+
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains("mempool_batches_stored_total 1\n"));
+    Ok(())
+}
+
+/// Check that the metrics are only served on loopback addresses
+#[tokio::test]
+async fn test_serve_metrics_loopback() -> anyhow::Result<()> {
+    let addr = format!("0.0.0.0:{}", METRICS_PORT + 1).parse()?;
+    assert!(serve_metrics(Registry::new(), addr).await.is_err());
+    Ok(())
+}
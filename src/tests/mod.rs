@@ -1,5 +1,22 @@
+mod admin;
+mod batch_store;
 mod common;
+mod config;
+mod helper;
+mod memory_store;
 mod mempool;
+mod metrics;
+mod msg;
+#[cfg(feature = "node")]
+mod node;
+#[cfg(feature = "node")]
+mod node_config;
+mod query;
+mod reassembler;
+mod round;
+mod scrubber;
 mod sealer;
+mod sim;
+mod synchronizer;
 
 pub(crate) use common::*;
@@ -0,0 +1,43 @@
+use super::{Id, Round, Tx};
+use crate::{Batch, BatchHeader, MempoolMsg};
+
+/// Check that framing a stored batch gives the same bytes as serializing the
+/// batch message
+#[test]
+fn test_frame_batch() {
+    let mut with_header = Batch::from(vec![Tx(true)]);
+    with_header.header = Some(BatchHeader::new::<Id, Round>(&1, 0, &3.into()));
+    for batch in [Batch::from(vec![Tx(true), Tx(false)]), with_header] {
+        let stored = bincode::serialize(&batch).unwrap();
+        let expected = bincode::serialize(&MempoolMsg::<Id, Tx>::Batch(batch)).unwrap();
+        assert_eq!(MempoolMsg::<Id, Tx>::frame_batch(&stored).to_vec(), expected);
+    }
+}
+
+/// Check that batches without a header keep the payload-only format, and that
+/// headers survive a round trip
+#[test]
+fn test_batch_header() {
+    #[derive(serde::Serialize)]
+    struct PayloadOnly {
+        payload: Vec<Tx>,
+    }
+
+    let legacy = bincode::serialize(&PayloadOnly {
+        payload: vec![Tx(true), Tx(false)],
+    })
+    .unwrap();
+    let batch: Batch<Tx> = bincode::deserialize(&legacy).unwrap();
+    assert_eq!(batch, Batch::from(vec![Tx(true), Tx(false)]));
+    assert_eq!(bincode::serialize(&batch).unwrap(), legacy);
+
+    let mut stamped = batch.clone();
+    stamped.header = Some(BatchHeader::new(&(3 as Id), 1, &Round::from(7)));
+    let serialized = bincode::serialize(&stamped).unwrap();
+    assert_ne!(serialized, legacy, "The header is not covered by the digest");
+    let deserialized: Batch<Tx> = bincode::deserialize(&serialized).unwrap();
+    assert_eq!(deserialized, stamped);
+    let header = deserialized.header.unwrap();
+    assert_eq!(header.author::<Id>().unwrap(), 3);
+    assert_eq!(header.round::<Round>().unwrap(), Round::from(7));
+}
@@ -0,0 +1,86 @@
+use super::{dummy_tx, Id, Round, Tx};
+use crate::{
+    parse_sample_marker, sample_marker, BatchHash, Committee, ConsensusEvent, MemoryStore, Member,
+    MempoolNode, NodeConfig, RawTx, Sampled,
+};
+use bytes::Bytes;
+use serde_json::json;
+use std::time::Duration;
+use tcp_sender::TcpSimpleSender;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time,
+};
+
+const NODE_BASE_PORT: u16 = 19_000;
+
+/// Check that a node seals the transactions of its clients, hands the batches
+/// to consensus, and answers its requests
+#[tokio::test]
+async fn test_node() -> anyhow::Result<()> {
+    let committee = Committee {
+        members: vec![Member {
+            id: 0,
+            mempool_addr: format!("127.0.0.1:{}", NODE_BASE_PORT).parse()?,
+            client_addr: format!("127.0.0.1:{}", NODE_BASE_PORT + 1).parse()?,
+        }],
+    };
+    let consensus_addr = format!("127.0.0.1:{}", NODE_BASE_PORT + 2);
+    let config: NodeConfig<Id, Round> = serde_json::from_value(json!({
+        "name": 0,
+        "consensus_addr": consensus_addr,
+        "sealer": "sized",
+        "benchmark": true,
+        "mempool": { "gc_depth": 50, "sealer": { "size": 1 } },
+    }))?;
+    let store = MemoryStore::new();
+    let _node = MempoolNode::<Round, _, Tx>::spawn(config, committee.clone(), store).await?;
+    time::sleep(Duration::from_millis(100)).await;
+
+    let (reader, mut writer) = TcpStream::connect(&consensus_addr).await?.into_split();
+    let mut events = BufReader::new(reader).lines();
+    let mut client = TcpSimpleSender::<Id, Tx>::with_peers(committee.client_peers());
+    let _ = client.send(0, Bytes::from(bincode::serialize(&dummy_tx())?)).await;
+
+    let line = time::timeout(Duration::from_secs(1), events.next_line()).await??;
+    let digest: BatchHash<Tx> = match serde_json::from_str(&line.unwrap())? {
+        ConsensusEvent::Batch { digest } => digest,
+        event => panic!("Expected a batch, got {:?}", event),
+    };
+
+    let request = json!({ "cmd": "unknown_batch", "source": 0, "digests": [digest] });
+    writer.write_all(format!("{}\n", request).as_bytes()).await?;
+    let line = time::timeout(Duration::from_secs(1), events.next_line()).await??;
+    match serde_json::from_str(&line.unwrap())? {
+        ConsensusEvent::<Tx>::Synced { digests, error } => {
+            assert_eq!(digests, vec![digest]);
+            assert_eq!(error, None);
+        }
+        event => panic!("Expected a sync notification, got {:?}", event),
+    }
+
+    writer.write_all(b"{\"cmd\": \"reboot\"}\n").await?;
+    let line = time::timeout(Duration::from_secs(1), events.next_line()).await??;
+    assert!(matches!(
+        serde_json::from_str(&line.unwrap())?,
+        ConsensusEvent::<Tx>::Error { .. }
+    ));
+    Ok(())
+}
+
+/// Check that the samples of the benchmark client are recognized, and that
+/// their markers can be read back
+#[test]
+fn test_samples() {
+    let sample = RawTx::sample(42, 512);
+    assert_eq!(sample.0.len(), 512);
+    assert_eq!(sample.sample_id(), Some(42));
+    assert_eq!(RawTx::standard(42, 512).sample_id(), None);
+
+    let line = format!("[2026-01-01T00:00:00Z INFO  libmempool_rs] {}", sample_marker(42));
+    let (id, ready) = parse_sample_marker(&line).unwrap();
+    assert_eq!(id, 42);
+    assert!(ready > 0);
+    assert_eq!(parse_sample_marker("Batch 42 is ready"), None);
+}
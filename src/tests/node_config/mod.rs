@@ -0,0 +1,85 @@
+use super::{Id, Round};
+use crate::{Committee, Member, NodeConfig, SealerKind};
+use std::time::Duration;
+
+fn committee(size: usize) -> Committee<Id> {
+    let members = (0..size)
+        .map(|id| Member {
+            id,
+            mempool_addr: format!("127.0.0.1:{}", 17_000 + id).parse().unwrap(),
+            client_addr: format!("127.0.0.1:{}", 18_000 + id).parse().unwrap(),
+        })
+        .collect();
+    Committee { members }
+}
+
+/// Check that a partial TOML file is completed with the defaults, and that the
+/// environment overrides it
+#[test]
+fn test_load_node_config() -> anyhow::Result<()> {
+    let path = std::env::temp_dir().join(format!("mempool-node-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        r#"
+            name = 1
+            worker = 2
+            sealer = "sized"
+
+            [mempool]
+            gc_depth = 50
+            sync_retry_delay = 200
+
+            [mempool.sealer]
+            size = 1000
+        "#,
+    )?;
+    let config = NodeConfig::<Id, Round>::load(&path);
+    std::fs::remove_file(&path)?;
+    let config = config?;
+    assert_eq!(config.name, 1);
+    assert_eq!(config.worker, 2);
+    assert_eq!(config.sealer, SealerKind::Sized);
+    assert_eq!(config.mempool.gc_depth, 50.into());
+    assert_eq!(config.mempool.sync_retry_delay, Duration::from_millis(200));
+    assert_eq!(config.mempool.sealer.size, 1000);
+    assert_eq!(config.mempool.sync_retry_nodes, 3, "Missing fields should take their default");
+
+    let vars = vec![
+        ("TEST_ADMIN_ADDR".to_string(), "127.0.0.1:7000".to_string()),
+        ("TEST_MEMPOOL__SEALER__TIMEOUT".to_string(), "20".to_string()),
+        ("OTHER_NAME".to_string(), "5".to_string()),
+    ];
+    let config = config.override_with(vars, "TEST_")?;
+    assert_eq!(config.name, 1);
+    assert_eq!(config.admin_addr, Some("127.0.0.1:7000".parse()?));
+    assert_eq!(config.mempool.sealer.timeout, Duration::from_millis(20));
+
+    let vars = vec![("TEST_MEMPOOL__GC_DEPTH".to_string(), "soon".to_string())];
+    assert!(config.override_with(vars, "TEST_").is_err(), "Invalid override was accepted");
+    Ok(())
+}
+
+/// Check that the config is validated against the committee
+#[test]
+fn test_validate_node_config() -> anyhow::Result<()> {
+    let json = r#"{ "name": 0, "mempool": { "gc_depth": 50 } }"#;
+    let config: NodeConfig<Id, Round> = serde_json::from_str(json)?;
+    config.validate(&committee(4))?;
+
+    let err = config.validate(&committee(2)).unwrap_err();
+    assert!(err.to_string().contains("sync_retry_nodes"), "Unexpected error: {}", err);
+
+    let stranger = NodeConfig { name: 7, ..config.clone() };
+    assert!(stranger.validate(&committee(4)).is_err(), "Node outside the committee");
+
+    let exposed = NodeConfig {
+        admin_addr: Some("0.0.0.0:7000".parse()?),
+        ..config.clone()
+    };
+    assert!(exposed.validate(&committee(4)).is_err(), "Admin endpoint is not on loopback");
+
+    let mut twice = committee(4);
+    twice.members[1].id = 0;
+    assert!(config.validate(&twice).is_err(), "Duplicate id in the committee");
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use super::Tx;
+use crate::{tx_hash, Batch, BatchStore, MemoryStore, MempoolQuery, Metrics, Processor};
+use tokio::sync::{mpsc::unbounded_channel, watch};
+
+/// Check that batches and transactions written by the processor can be queried
+#[tokio::test]
+async fn test_query() -> anyhow::Result<()> {
+    let store = BatchStore::new(MemoryStore::new());
+    let (tx_processor, rx_processor) = unbounded_channel();
+    let (_tx_synced, rx_synced) = unbounded_channel();
+    let (tx_hash_out, mut rx_hash) = unbounded_channel();
+    let (tx_gc, _rx_gc) = unbounded_channel();
+    let (tx_query, _rx_query) = unbounded_channel();
+    let (_tx_report, rx_report) = watch::channel(None);
+
+    Processor::<MemoryStore, Tx>::spawn(
+        store.clone(),
+        rx_processor,
+        rx_synced,
+        tx_hash_out,
+        tx_gc,
+        Metrics::default(),
+    );
+    let mut query = MempoolQuery::new(store, tx_query, rx_report);
+
+    let batch = Batch::from(vec![Tx(true)]);
+    tx_processor.send(batch.clone())?;
+    let hash = rx_hash.recv().await.unwrap();
+
+    assert!(query.contains(&hash).await?);
+    assert_eq!(query.batch(&hash).await?, Some(batch));
+    assert_eq!(query.transaction(&tx_hash(&Tx(true))).await?, Some(Tx(true)));
+    assert_eq!(query.transaction(&tx_hash(&Tx(false))).await?, None);
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use super::{get_peers, Id, Tx};
+use crate::{Batch, BatchChunk, BatchHash, Reassembler, SynchronizerQuery};
+use libcrypto::hash::Hash;
+use std::time::Duration;
+use tcp_sender::TcpSimpleSender;
+use tokio::{sync::mpsc::unbounded_channel, time};
+
+const REASSEMBLER_BASE_PORT: u16 = 13_000;
+const CHUNK_SIZE: usize = 4;
+const MAX_BATCH_SIZE: usize = 1_000;
+
+/// Splits a serialized batch into chunks
+fn chunks(
+    digest: &BatchHash<Tx>,
+    data: &[u8],
+) -> Vec<BatchChunk<Tx>> {
+    data.chunks(CHUNK_SIZE)
+        .enumerate()
+        .map(|(i, part)| BatchChunk {
+            digest: digest.clone(),
+            offset: (i * CHUNK_SIZE) as u64,
+            total: data.len() as u64,
+            window_end: false,
+            data: part.to_vec(),
+        })
+        .collect()
+}
+
+/// Check that chunks are put back together, and that duplicates and corrupted
+/// batches are handled
+#[tokio::test]
+async fn test_reassembly() -> anyhow::Result<()> {
+    let batch = Batch::from(vec![Tx(true), Tx(false), Tx(true)]);
+    let data = bincode::serialize(&batch)?;
+    let digest: BatchHash<Tx> = Hash::do_hash(&data);
+
+    // Play the synchronizer, which only waits for `digest`
+    let (tx_query, mut rx_query) = unbounded_channel();
+    let pending = digest.clone();
+    tokio::spawn(async move {
+        while let Some(query) = rx_query.recv().await {
+            match query {
+                SynchronizerQuery::IsPending(hash, tx_reply) => {
+                    let _ = tx_reply.send(hash == pending);
+                }
+                _ => panic!("Unexpected query"),
+            }
+        }
+    });
+
+    let (tx_chunk, rx_chunk) = unbounded_channel();
+    let (tx_processor, mut rx_processor) = unbounded_channel();
+    Reassembler::<Id, Tx>::spawn(
+        0,
+        TcpSimpleSender::with_peers(get_peers(2, REASSEMBLER_BASE_PORT)),
+        rx_chunk,
+        tx_processor,
+        tx_query,
+        MAX_BATCH_SIZE,
+    );
+
+    // A batch that we did not ask for is ignored
+    let unwanted = Batch::from(vec![Tx(false), Tx(false), Tx(false)]);
+    for part in chunks(&unwanted.digest(), &bincode::serialize(&unwanted)?) {
+        tx_chunk.send((1, part))?;
+    }
+
+    // So is a stream announcing a batch larger than the limit
+    let mut oversized = chunks(&digest, &data).remove(0);
+    oversized.total = MAX_BATCH_SIZE as u64 + 1;
+    tx_chunk.send((1, oversized))?;
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(rx_processor.try_recv().is_err(), "Unwanted batch was forwarded");
+
+    let parts = chunks(&digest, &data);
+    assert!(parts.len() > 2, "The batch should span several chunks");
+
+    // Send the first chunk twice, as if two helpers answered
+    tx_chunk.send((1, parts[0].clone()))?;
+    for part in parts {
+        tx_chunk.send((1, part))?;
+    }
+    let received = time::timeout(Duration::from_secs(1), rx_processor.recv()).await?;
+    assert_eq!(received, Some(batch));
+
+    // A batch that does not match its digest is dropped
+    let other = bincode::serialize(&Batch::from(vec![Tx(false)]))?;
+    for part in chunks(&digest, &other) {
+        tx_chunk.send((1, part))?;
+    }
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(rx_processor.try_recv().is_err(), "Corrupted batch was forwarded");
+    Ok(())
+}
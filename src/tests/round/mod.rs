@@ -0,0 +1,114 @@
+use crate::Round;
+use proptest::prelude::*;
+
+/// Generates the property tests for the checked and saturating arithmetic of
+/// a built-in round type
+macro_rules! test_round {
+    ($name: ident, $tp: ty) => {
+        mod $name {
+            use super::*;
+
+            const MIN: $tp = <$tp as Round>::MIN;
+
+            proptest! {
+                #[test]
+                fn checked_sub(a in MIN..=<$tp>::MAX, b in MIN..=<$tp>::MAX) {
+                    match <$tp as Round>::checked_sub(a, b) {
+                        Some(res) => {
+                            prop_assert!(res >= MIN);
+                            prop_assert_eq!(res + b, a);
+                        }
+                        None => prop_assert!(a < b),
+                    }
+                }
+
+                #[test]
+                fn saturating_sub(a in MIN..=<$tp>::MAX, b in MIN..=<$tp>::MAX) {
+                    let res = <$tp as Round>::saturating_sub(a, b);
+                    if a >= b {
+                        prop_assert_eq!(res, a - b);
+                    } else {
+                        prop_assert_eq!(res, MIN);
+                    }
+                }
+
+                #[test]
+                fn gc_round_is_monotonic(
+                    a in MIN..=<$tp>::MAX,
+                    b in MIN..=<$tp>::MAX,
+                    gc_depth in MIN..=<$tp>::MAX,
+                ) {
+                    let (lo, hi) = (a.min(b), a.max(b));
+                    prop_assert!(
+                        <$tp as Round>::saturating_sub(lo, gc_depth)
+                            <= <$tp as Round>::saturating_sub(hi, gc_depth)
+                    );
+                }
+            }
+        }
+    };
+}
+
+/// Generates the property tests of a signed round type over its whole range,
+/// negative rounds included
+macro_rules! test_signed_round {
+    ($name: ident, $tp: ty) => {
+        mod $name {
+            use super::*;
+
+            const MIN: $tp = <$tp as Round>::MIN;
+
+            proptest! {
+                #[test]
+                fn checked_sub(a in <$tp>::MIN..=<$tp>::MAX, b in <$tp>::MIN..=<$tp>::MAX) {
+                    match <$tp as Round>::checked_sub(a, b) {
+                        Some(res) => {
+                            prop_assert!(res >= MIN);
+                            prop_assert_eq!(res + b, a);
+                        }
+                        None => prop_assert!(!matches!(a.checked_sub(b), Some(res) if res >= MIN)),
+                    }
+                }
+
+                #[test]
+                fn saturating_sub(a in <$tp>::MIN..=<$tp>::MAX, b in <$tp>::MIN..=<$tp>::MAX) {
+                    let res = <$tp as Round>::saturating_sub(a, b);
+                    prop_assert!(res >= MIN);
+                    match a.checked_sub(b) {
+                        Some(diff) if diff >= MIN => prop_assert_eq!(res, diff),
+                        _ => prop_assert_eq!(res, MIN),
+                    }
+                }
+            }
+        }
+    };
+}
+
+/// Check the default `checked_sub`, through the round type of the tests
+#[test]
+fn test_default_checked_sub() {
+    use super::Round as TestRound;
+
+    let round = |n: usize| TestRound::from(n);
+    assert_eq!(round(5).checked_sub(round(3)), Some(round(2)));
+    assert_eq!(round(3).checked_sub(round(3)), Some(round(0)));
+    assert_eq!(round(1).checked_sub(round(3)), None);
+    assert_eq!(round(1).saturating_sub(round(3)), round(0));
+}
+
+test_round!(round_u8, u8);
+test_round!(round_u16, u16);
+test_round!(round_u32, u32);
+test_round!(round_u64, u64);
+test_round!(round_u128, u128);
+test_round!(round_i8, i8);
+test_round!(round_i16, i16);
+test_round!(round_i32, i32);
+test_round!(round_i64, i64);
+test_round!(round_i128, i128);
+
+test_signed_round!(signed_round_i8, i8);
+test_signed_round!(signed_round_i16, i16);
+test_signed_round!(signed_round_i32, i32);
+test_signed_round!(signed_round_i64, i64);
+test_signed_round!(signed_round_i128, i128);
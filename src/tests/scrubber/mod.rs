@@ -0,0 +1,48 @@
+use super::Tx;
+use crate::{Batch, BatchIndex, BatchStore, MemoryStore, Metrics, Scrubber};
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc::unbounded_channel, watch},
+    time,
+};
+
+const SCRUB_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Check that corrupted batches are quarantined and re-synced, and that the
+/// others are left alone
+#[tokio::test]
+async fn test_scrub() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let (tx_resync, mut rx_resync) = unbounded_channel();
+    let (tx_report, mut rx_report) = watch::channel(None);
+    Scrubber::<MemoryStore, Tx>::spawn(
+        store.clone(),
+        SCRUB_INTERVAL,
+        tx_resync,
+        tx_report,
+        Metrics::default(),
+    );
+
+    let good_digest = store.put(&Batch::from(vec![Tx(true)])).await;
+
+    let mut bad = BatchStore::<MemoryStore, Tx>::encode(&Batch::from(vec![Tx(false)]));
+    let bad_digest = BatchStore::<MemoryStore, Tx>::digest(&bad);
+    let last = bad.len() - 1;
+    bad[last] ^= 1;
+    store.put_raw(&bad_digest, bad).await;
+
+    // Play the synchronizer, which indexes the stored batches
+    let mut index = BatchIndex::load(store.clone()).await?;
+    index.add(&good_digest).await?;
+    index.add(&bad_digest).await?;
+
+    time::timeout(Duration::from_secs(1), rx_report.changed()).await??;
+    let report = rx_report.borrow().clone().unwrap();
+    assert_eq!(report.scanned, 2);
+    assert_eq!(report.corrupted, vec![bad_digest.clone()]);
+
+    assert_eq!(rx_resync.recv().await, Some(bad_digest.clone()));
+    assert!(!store.exists(&bad_digest).await?, "Corrupted batch was not quarantined");
+    assert!(store.exists(&good_digest).await?, "Good batch was removed");
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use super::{dummy_tx, Id, Round, Tx};
+use crate::{
+    batcher::Batcher, sealer::Sized, Config, ConfigUpdater, ConsensusMempoolMsg, MemoryStore,
+    Mempool, MempoolMsg, Metrics, SimConfig, SimNetwork, SimReceiver, SimSender,
+};
+use bytes::Bytes;
+use fnv::FnvHashMap;
+use futures::StreamExt;
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc::unbounded_channel, oneshot},
+    time,
+};
+
+const PORT: u16 = 9_000;
+const MEMPOOL_PORT: u16 = 10_000;
+
+fn host(i: u8) -> IpAddr {
+    IpAddr::from([10, 0, 0, i])
+}
+
+fn addr(i: u8) -> SocketAddr {
+    SocketAddr::new(host(i), PORT)
+}
+
+/// Connects host 0 to the receivers of hosts 1 and 2
+fn setup(config: SimConfig) -> (SimNetwork, SimSender<u8, Tx>, Vec<SimReceiver<Tx>>) {
+    let network = SimNetwork::new(config);
+    let peers: FnvHashMap<u8, SocketAddr> = (1..=2).map(|i| (i, addr(i))).collect();
+    let sender = network.sender(host(0), peers);
+    let receivers = (1..=2).map(|i| network.receiver(addr(i))).collect();
+    (network, sender, receivers)
+}
+
+fn encode(tx: Tx) -> Bytes {
+    Bytes::from(bincode::serialize(&tx).unwrap())
+}
+
+/// Sends alternating transactions, and returns them in the order they arrive
+async fn run(config: SimConfig) -> Vec<bool> {
+    let (_network, mut sender, mut receivers) = setup(config);
+    for i in 0..20 {
+        sender.send(1, encode(Tx(i % 2 == 0))).await.unwrap();
+    }
+    let mut received = Vec::new();
+    for _ in 0..20 {
+        received.push(receivers[0].next().await.unwrap().unwrap().0);
+    }
+    received
+}
+
+/// Check that links are FIFO by default, and that reordering is the same for
+/// the same seed
+#[tokio::test(start_paused = true)]
+async fn test_sim_order() {
+    let sent: Vec<bool> = (0..20).map(|i| i % 2 == 0).collect();
+    assert_eq!(run(SimConfig::default()).await, sent);
+
+    let reordered = |seed| SimConfig {
+        seed,
+        reorder: true,
+        ..SimConfig::default()
+    };
+    let first = run(reordered(7)).await;
+    assert_ne!(first, sent);
+    assert_eq!(run(reordered(7)).await, first);
+}
+
+/// Check that messages are delayed, and lost between partitions or at random
+#[tokio::test(start_paused = true)]
+async fn test_sim_faults() {
+    let config = SimConfig {
+        min_delay: Duration::from_millis(50),
+        max_delay: Duration::from_millis(50),
+        ..SimConfig::default()
+    };
+    let (network, mut sender, mut receivers) = setup(config);
+    sender.send(1, encode(dummy_tx())).await.unwrap();
+    assert!(time::timeout(Duration::from_millis(49), receivers[0].next()).await.is_err());
+    assert!(receivers[0].next().await.is_some());
+
+    network.partition(&[vec![host(0), host(1)]]);
+    sender.send(1, encode(dummy_tx())).await.unwrap();
+    sender.send(2, encode(dummy_tx())).await.unwrap();
+    assert!(receivers[0].next().await.is_some());
+    assert!(time::timeout(Duration::from_secs(1), receivers[1].next()).await.is_err());
+
+    network.heal();
+    sender.send(2, encode(dummy_tx())).await.unwrap();
+    assert!(receivers[1].next().await.is_some());
+    assert!(sender.send(3, encode(dummy_tx())).await.is_err());
+
+    let stats = network.stats();
+    assert_eq!((stats.sent, stats.delivered, stats.dropped), (4, 3, 1));
+
+    let lossy = SimConfig {
+        drop_rate: 1.0,
+        ..SimConfig::default()
+    };
+    let (network, mut sender, _receivers) = setup(lossy);
+    for _ in 0..10 {
+        sender.send(1, encode(dummy_tx())).await.unwrap();
+    }
+    assert_eq!(network.stats().dropped, 10);
+}
+
+/// Check that mempools batch the transactions of a client and synchronize the
+/// batches of each other over the simulated network, including across a
+/// partition that heals
+#[tokio::test(start_paused = true)]
+async fn test_sim_mempool() -> anyhow::Result<()> {
+    let num_nodes: u8 = 4;
+    let network = SimNetwork::new(SimConfig {
+        seed: 42,
+        drop_rate: 0.05,
+        ..SimConfig::default()
+    });
+    let mempool_peers: FnvHashMap<Id, SocketAddr> = (0..num_nodes)
+        .map(|i| (i as Id, SocketAddr::new(host(i), MEMPOOL_PORT)))
+        .collect();
+    let all_ids: Vec<Id> = (0..num_nodes as Id).collect();
+    let config = ConfigUpdater::new(Config::<Round> {
+        gc_depth: 2.into(),
+        ..Default::default()
+    })?;
+
+    let mut consensus = Vec::new();
+    let mut outputs = Vec::new();
+    for i in 0..num_nodes {
+        let (tx_consensus, rx_consensus) = unbounded_channel();
+        let (tx_batcher, rx_batcher) = unbounded_channel();
+        let (tx_processor, rx_processor) = unbounded_channel();
+        let (tx_output, rx_output) = unbounded_channel();
+        let metrics = Metrics::default();
+        Batcher::spawn(rx_batcher, tx_processor.clone(), Sized::new(1), metrics.clone());
+        Mempool::spawn(
+            i as Id,
+            all_ids.clone(),
+            config.subscribe(),
+            metrics,
+            MemoryStore::new(),
+            network.sender::<Id, MempoolMsg<Id, Tx>>(host(i), mempool_peers.clone()),
+            rx_consensus,
+            tx_batcher,
+            tx_processor,
+            rx_processor,
+            tx_output,
+            SocketAddr::new(host(i), MEMPOOL_PORT),
+            addr(i),
+        );
+        consensus.push(tx_consensus);
+        outputs.push(rx_output);
+    }
+
+    // A client sends a transaction to the first node, which seals it alone
+    let client_peers = [(0, addr(0))].into_iter().collect();
+    let mut client = network.sender::<Id, Tx>(host(100), client_peers);
+    client.send(0, encode(dummy_tx())).await?;
+    let digest = outputs[0].recv().await.unwrap();
+
+    // The last node cannot reach anyone until the partition heals
+    network.partition(&[vec![host(num_nodes - 1)]]);
+    let mut synced = Vec::new();
+    for tx_consensus in &consensus[1..] {
+        let (tx_done, rx_done) = oneshot::channel();
+        let request = ConsensusMempoolMsg::UnknownBatch(0, vec![digest.clone()], vec![], tx_done);
+        tx_consensus.send(request)?;
+        synced.push(rx_done);
+    }
+    let isolated = synced.pop().unwrap();
+    for rx_done in synced {
+        rx_done.await??;
+    }
+    time::sleep(Duration::from_secs(2)).await;
+    network.heal();
+    isolated.await??;
+    assert!(network.stats().dropped > 0);
+    Ok(())
+}
@@ -0,0 +1,365 @@
+use super::{get_peers, Id, Round, Tx};
+use crate::{
+    synchronizer::PendingBatch, Batch, BatchHash, BatchHeader, BatchStore, Config,
+    ConsensusMempoolMsg, MemoryStore, Metrics, Processed, Synchronizer,
+};
+use fnv::FnvHashMap;
+use libcrypto::hash::Hash;
+use std::time::Duration;
+use tcp_sender::TcpSimpleSender;
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot, watch,
+    },
+    time,
+};
+
+const SYNC_BASE_PORT: u16 = 12_000;
+const WAIT_TIME: Duration = Duration::from_millis(50);
+const MAX_WAIT_TIME: Duration = Duration::from_millis(200);
+const MAX_ATTEMPTS: usize = 2;
+
+/// The channels used to drive a synchronizer under test
+struct TestSynchronizer {
+    tx_consensus: UnboundedSender<ConsensusMempoolMsg<Id, Round, Tx>>,
+    tx_processed: UnboundedSender<Processed<Tx>>,
+    rx_batcher: UnboundedReceiver<(Tx, usize)>,
+    tx_config: watch::Sender<Config<Round>>,
+}
+
+fn spawn_synchronizer(
+    store: &BatchStore<MemoryStore, Tx>,
+    gc_depth: Round,
+    reinject_depth: Option<Round>,
+    port: u16,
+) -> TestSynchronizer {
+    let (tx_consensus, rx_consensus) = unbounded_channel();
+    let (tx_processed, rx_processed) = unbounded_channel();
+    let (tx_batcher, rx_batcher) = unbounded_channel();
+    let (_tx_query, rx_query) = unbounded_channel();
+    let (_tx_resync, rx_resync) = unbounded_channel();
+    let (tx_config, rx_config) = watch::channel(Config {
+        gc_depth,
+        sync_retry_delay: WAIT_TIME,
+        sync_retry_max_delay: MAX_WAIT_TIME,
+        sync_retry_max_attempts: MAX_ATTEMPTS,
+        sync_retry_nodes: 1,
+        reinject_depth,
+        ..Default::default()
+    });
+
+    Synchronizer::<Id, Round, Tx, MemoryStore>::spawn(
+        0,
+        rx_consensus,
+        rx_processed,
+        rx_query,
+        rx_resync,
+        rx_config,
+        TcpSimpleSender::with_peers(get_peers(1, port)),
+        store.clone(),
+        vec![0],
+        tx_batcher,
+        Metrics::default(),
+    );
+
+    TestSynchronizer {
+        tx_consensus,
+        tx_processed,
+        rx_batcher,
+        tx_config,
+    }
+}
+
+/// Writes a batch to the store and returns its digest
+async fn store_batch(
+    store: &mut BatchStore<MemoryStore, Tx>,
+    txs: Vec<Tx>,
+) -> BatchHash<Tx> {
+    store.put(&Batch::from(txs)).await
+}
+
+/// The report of the processor for a batch that we sealed
+fn sealed(digest: &BatchHash<Tx>) -> Processed<Tx> {
+    Processed {
+        digest: digest.clone(),
+        own: true,
+        header: None,
+    }
+}
+
+/// Check that old batches are deleted and pinned batches are kept
+#[tokio::test]
+async fn test_gc() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let sync = spawn_synchronizer(&store, 2.into(), None, SYNC_BASE_PORT);
+
+    let old = store_batch(&mut store, vec![Tx(true)]).await;
+    let pinned = store_batch(&mut store, vec![Tx(false)]).await;
+    sync.tx_processed.send(sealed(&old))?;
+    sync.tx_processed.send(sealed(&pinned))?;
+    sync.tx_consensus.send(ConsensusMempoolMsg::Pin(vec![pinned.clone()]))?;
+    time::sleep(WAIT_TIME).await;
+
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(5.into()))?;
+    time::sleep(WAIT_TIME).await;
+
+    assert!(!store.exists(&old).await?, "Old batch was not gc'ed");
+    assert!(store.exists(&pinned).await?, "Pinned batch was gc'ed");
+    Ok(())
+}
+
+/// Check that ending a round earlier than the gc depth does not underflow the
+/// gc round, which used to panic
+#[tokio::test]
+async fn test_gc_early_round() -> anyhow::Result<()> {
+    let store = BatchStore::new(MemoryStore::new());
+    let sync = spawn_synchronizer(&store, 10.into(), None, SYNC_BASE_PORT + 8);
+
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(1.into()))?;
+    time::sleep(WAIT_TIME).await;
+    assert!(
+        sync.tx_consensus.send(ConsensusMempoolMsg::End(2.into())).is_ok(),
+        "Synchronizer died on a round earlier than the gc depth"
+    );
+    Ok(())
+}
+
+/// Check that a gc depth of `Round::MIN` keeps every batch
+#[tokio::test]
+async fn test_gc_disabled() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let sync = spawn_synchronizer(&store, 0.into(), None, SYNC_BASE_PORT + 7);
+
+    let digest = store_batch(&mut store, vec![Tx(true)]).await;
+    sync.tx_processed.send(sealed(&digest))?;
+    time::sleep(WAIT_TIME).await;
+
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(5.into()))?;
+    time::sleep(WAIT_TIME).await;
+    assert!(store.exists(&digest).await?, "Batch was gc'ed with gc disabled");
+    Ok(())
+}
+
+/// Check that batches are collected by the round of their header, rather than
+/// by the round in which we stored them
+#[tokio::test]
+async fn test_gc_header_round() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let sync = spawn_synchronizer(&store, 2.into(), None, SYNC_BASE_PORT + 6);
+
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(5.into()))?;
+    time::sleep(WAIT_TIME).await;
+
+    let mut old = Batch::from(vec![Tx(true)]);
+    old.header = Some(BatchHeader::new::<Id, Round>(&1, 0, &1.into()));
+    let old = store.put(&old).await;
+    let recent = store_batch(&mut store, vec![Tx(false)]).await;
+    for digest in [&old, &recent] {
+        sync.tx_processed.send(Processed {
+            digest: digest.clone(),
+            own: false,
+            header: store.get(digest).await?.unwrap().header,
+        })?;
+    }
+    time::sleep(WAIT_TIME).await;
+
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(6.into()))?;
+    time::sleep(WAIT_TIME).await;
+
+    assert!(!store.exists(&old).await?, "Batch of an old round was not gc'ed");
+    assert!(store.exists(&recent).await?, "Recent batch was gc'ed");
+    Ok(())
+}
+
+/// Check that only the transactions of uncommitted batches are re-injected, and
+/// that transactions that were already committed are not
+#[tokio::test]
+async fn test_reinject() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let mut sync = spawn_synchronizer(&store, 10.into(), Some(2.into()), SYNC_BASE_PORT + 1);
+
+    let committed = store_batch(&mut store, vec![Tx(true), Tx(true)]).await;
+    let dropped = store_batch(&mut store, vec![Tx(false), Tx(true)]).await;
+    sync.tx_processed.send(sealed(&committed))?;
+    sync.tx_processed.send(sealed(&dropped))?;
+    time::sleep(WAIT_TIME).await;
+
+    sync.tx_consensus.send(ConsensusMempoolMsg::Committed(1.into(), vec![committed]))?;
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(1.into()))?;
+    time::sleep(WAIT_TIME).await;
+    assert!(sync.rx_batcher.try_recv().is_err(), "Re-injected too early");
+
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(2.into()))?;
+    let (tx, _) = sync.rx_batcher.recv().await.unwrap();
+    assert_eq!(tx, Tx(false));
+    time::sleep(WAIT_TIME).await;
+    assert!(sync.rx_batcher.try_recv().is_err(), "Committed transaction was re-injected");
+    Ok(())
+}
+
+/// Check that the transactions committed while re-injection was disabled are
+/// not re-injected once it is enabled
+#[tokio::test]
+async fn test_reinject_enabled_later() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let mut sync = spawn_synchronizer(&store, 10.into(), None, SYNC_BASE_PORT + 4);
+
+    let committed = store_batch(&mut store, vec![Tx(true)]).await;
+    let dropped = store_batch(&mut store, vec![Tx(false), Tx(true)]).await;
+    sync.tx_processed.send(sealed(&committed))?;
+    sync.tx_processed.send(sealed(&dropped))?;
+    time::sleep(WAIT_TIME).await;
+    sync.tx_consensus.send(ConsensusMempoolMsg::Committed(1.into(), vec![committed]))?;
+    time::sleep(WAIT_TIME).await;
+
+    sync.tx_config.send_modify(|config| config.reinject_depth = Some(1.into()));
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(2.into()))?;
+    let (tx, _) = sync.rx_batcher.recv().await.unwrap();
+    assert_eq!(tx, Tx(false));
+    time::sleep(WAIT_TIME).await;
+    assert!(sync.rx_batcher.try_recv().is_err(), "Committed transaction was re-injected");
+    Ok(())
+}
+
+/// Check that batches are re-injected before they are garbage collected, even
+/// if the re-inject depth is deeper than the gc depth
+#[tokio::test]
+async fn test_reinject_before_gc() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let mut sync = spawn_synchronizer(&store, 2.into(), Some(5.into()), SYNC_BASE_PORT + 9);
+
+    let dropped = store_batch(&mut store, vec![Tx(false)]).await;
+    sync.tx_processed.send(sealed(&dropped))?;
+    time::sleep(WAIT_TIME).await;
+
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(3.into()))?;
+    let (tx, _) = time::timeout(Duration::from_secs(1), sync.rx_batcher.recv()).await?.unwrap();
+    assert_eq!(tx, Tx(false));
+    time::sleep(WAIT_TIME).await;
+    assert!(!store.exists(&dropped).await?, "Batch was not gc'ed");
+    Ok(())
+}
+
+/// Check that the transactions of the batches of other nodes are not
+/// re-injected
+#[tokio::test]
+async fn test_reinject_own_only() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let mut sync = spawn_synchronizer(&store, 10.into(), Some(1.into()), SYNC_BASE_PORT + 5);
+
+    let foreign = store_batch(&mut store, vec![Tx(false)]).await;
+    sync.tx_processed.send(Processed {
+        digest: foreign,
+        own: false,
+        header: None,
+    })?;
+    time::sleep(WAIT_TIME).await;
+
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(2.into()))?;
+    time::sleep(WAIT_TIME).await;
+    assert!(sync.rx_batcher.try_recv().is_err(), "Batch of another node was re-injected");
+    Ok(())
+}
+
+/// Check that consensus is notified once the missing batches arrive, and that
+/// the notification fails if they are garbage collected first
+#[tokio::test]
+async fn test_sync_notification() -> anyhow::Result<()> {
+    let mut store = BatchStore::new(MemoryStore::new());
+    let sync = spawn_synchronizer(&store, 2.into(), None, SYNC_BASE_PORT + 2);
+
+    let batch = Batch::from(vec![Tx(true)]);
+    let hash: BatchHash<Tx> = Hash::do_hash(&bincode::serialize(&batch)?);
+    let (tx_done, mut rx_done) = oneshot::channel();
+    sync.tx_consensus.send(ConsensusMempoolMsg::UnknownBatch(0, vec![hash], vec![], tx_done))?;
+    time::sleep(WAIT_TIME).await;
+    assert!(rx_done.try_recv().is_err(), "Notified before the batch arrived");
+
+    store_batch(&mut store, vec![Tx(true)]).await;
+    assert!(rx_done.await?.is_ok(), "Sync request should have succeeded");
+
+    let lost: BatchHash<Tx> = Hash::do_hash(&bincode::serialize(&Batch::from(vec![Tx(false)]))?);
+    let (tx_done, rx_done) = oneshot::channel();
+    sync.tx_consensus.send(ConsensusMempoolMsg::UnknownBatch(0, vec![lost], vec![], tx_done))?;
+    sync.tx_consensus.send(ConsensusMempoolMsg::End(5.into()))?;
+    assert!(rx_done.await?.is_err(), "Sync request should have been garbage collected");
+    Ok(())
+}
+
+/// Check that we give up on a batch after the maximum number of retries
+#[tokio::test]
+async fn test_sync_max_attempts() -> anyhow::Result<()> {
+    let store = BatchStore::new(MemoryStore::new());
+    let sync = spawn_synchronizer(&store, 2.into(), None, SYNC_BASE_PORT + 3);
+
+    let lost: BatchHash<Tx> = Hash::do_hash(&bincode::serialize(&Batch::from(vec![Tx(false)]))?);
+    let (tx_done, rx_done) = oneshot::channel();
+    sync.tx_consensus.send(ConsensusMempoolMsg::UnknownBatch(0, vec![lost], vec![], tx_done))?;
+    let res = time::timeout(Duration::from_secs(2), rx_done).await??;
+    assert!(res.is_err(), "Sync request should have failed");
+    Ok(())
+}
+
+/// Check that retries never go to ourselves and rotate through the peers
+#[test]
+fn test_select_peers() {
+    let (tx_cancel, _rx_cancel) = unbounded_channel();
+    let all_ids: Vec<Id> = (0..5).collect();
+    let stats = FnvHashMap::default();
+    let mut batch = PendingBatch::<Id, Round>::new(Round::MIN, tx_cancel, 1, vec![], WAIT_TIME);
+
+    let mut asked = Vec::new();
+    asked.extend(batch.select_peers(&all_ids, &0, 2, &stats));
+    asked.extend(batch.select_peers(&all_ids, &0, 2, &stats));
+    asked.sort();
+    assert_eq!(asked, vec![2, 3, 4], "Should only ask the peers that were not tried");
+
+    let again = batch.select_peers(&all_ids, &0, 4, &stats);
+    assert_eq!(again.len(), 4, "Should start over once every peer was tried");
+    assert!(!again.contains(&0), "Should never ask ourselves");
+}
+
+/// Check that the likely holders are asked first, then the peers that served
+/// us best
+#[test]
+fn test_select_peers_preference() {
+    let (tx_cancel, _rx_cancel) = unbounded_channel();
+    let all_ids: Vec<Id> = (0..6).collect();
+    let mut stats = FnvHashMap::default();
+    let mut batch = PendingBatch::<Id, Round>::new(Round::MIN, tx_cancel, 1, vec![4], WAIT_TIME);
+
+    // Peer 2 failed us before, peer 3 answered quickly
+    let (tx_cancel, _rx_cancel) = unbounded_channel();
+    let mut other = PendingBatch::<Id, Round>::new(Round::MIN, tx_cancel, 2, vec![], WAIT_TIME);
+    other.backoff(WAIT_TIME, MAX_WAIT_TIME, &mut stats);
+    let (tx_cancel, _rx_cancel) = unbounded_channel();
+    let mut other = PendingBatch::<Id, Round>::new(Round::MIN, tx_cancel, 3, vec![], WAIT_TIME);
+    other.delivered(&mut stats);
+
+    assert_eq!(batch.select_peers(&all_ids, &0, 3, &stats), vec![4, 3, 2]);
+    assert_eq!(batch.select_peers(&all_ids, &0, 3, &stats), vec![5]);
+}
+
+/// Check that the retry delay starts at the base delay, and grows
+/// exponentially up to the maximum
+#[test]
+fn test_backoff() {
+    let (tx_cancel, _rx_cancel) = unbounded_channel();
+    let mut stats = FnvHashMap::default();
+    let mut batch = PendingBatch::<Id, Round>::new(Round::MIN, tx_cancel, 1, vec![], WAIT_TIME);
+
+    for attempt in 0..5u32 {
+        let start = time::Instant::now();
+        batch.backoff(WAIT_TIME, MAX_WAIT_TIME, &mut stats);
+        let delay = batch.next_retry - start;
+        let expected = (WAIT_TIME * 2u32.pow(attempt)).min(MAX_WAIT_TIME);
+        assert!(delay >= expected, "Retry {} is too early", attempt + 1);
+        assert!(
+            delay <= expected * 3 / 2 + Duration::from_millis(1),
+            "Retry {} is too late",
+            attempt + 1
+        );
+    }
+    assert_eq!(batch.attempts, 5);
+}
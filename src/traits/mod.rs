@@ -1,3 +1,4 @@
+use crate::SealerParams;
 use futures::Future;
 use serde::{de::DeserializeOwned, Serialize};
 use std::fmt::Debug;
@@ -24,28 +25,71 @@ pub trait Round:
     + core::fmt::Display
 {
     const MIN: Self;
+
+    /// The gc depth of the default config. It is `MIN` unless overridden,
+    /// which the default config then fails to validate: the gc depth must be
+    /// set.
+    const DEFAULT_GC_DEPTH: Self = Self::MIN;
+
+    /// Computes `self - rhs`, returning `None` if the result would be smaller
+    /// than `Self::MIN`
+    ///
+    /// The default assumes that rounds are never negative, so that `self - rhs`
+    /// only underflows if `rhs > self`. Types with negative rounds override it.
+    fn checked_sub(
+        self,
+        rhs: Self,
+    ) -> Option<Self> {
+        if rhs > self {
+            return None;
+        }
+        Some(self - rhs).filter(|res| *res >= Self::MIN)
+    }
+
+    /// Computes `self - rhs`, clamping the result at `Self::MIN`
+    fn saturating_sub(
+        self,
+        rhs: Self,
+    ) -> Self {
+        self.checked_sub(rhs).unwrap_or(Self::MIN)
+    }
 }
 
 macro_rules! implement_round {
-    ($tp: ty, $default: literal) => {
+    ($tp: ty, $default: literal, $gc_depth: literal) => {
         impl crate::Round for $tp {
             const MIN: $tp = $default;
+            const DEFAULT_GC_DEPTH: $tp = $gc_depth;
+
+            fn checked_sub(
+                self,
+                rhs: Self,
+            ) -> Option<Self> {
+                // NOTE: `Self::MIN` would resolve to the inherent constant here
+                <$tp>::checked_sub(self, rhs)
+                    .filter(|res| res.ge(&<Self as crate::Round>::MIN))
+            }
         }
     };
 }
 
-implement_round!(u8, 0);
-implement_round!(u16, 0);
-implement_round!(u32, 0);
-implement_round!(u64, 0);
-implement_round!(u128, 0);
-implement_round!(i8, 0);
-implement_round!(i16, 0);
-implement_round!(i32, 0);
-implement_round!(i64, 0);
-implement_round!(i128, 0);
+implement_round!(u8, 0, 50);
+implement_round!(u16, 0, 50);
+implement_round!(u32, 0, 50);
+implement_round!(u64, 0, 50);
+implement_round!(u128, 0, 50);
+implement_round!(i8, 0, 50);
+implement_round!(i16, 0, 50);
+implement_round!(i32, 0, 50);
+implement_round!(i64, 0, 50);
+implement_round!(i128, 0, 50);
 
 pub trait Sealer<Tx>: Send + Sync + 'static + Future<Output = Vec<Tx>> + Unpin {
+    /// The name of the sealer, to tell the batches apart in the metrics
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
     /// Cleans the sealer and returns all transactions
     fn seal(&mut self) -> Vec<Tx>;
 
@@ -56,4 +100,12 @@ pub trait Sealer<Tx>: Send + Sync + 'static + Future<Output = Vec<Tx>> + Unpin {
         _tx_size: usize,
     ) {
     }
+
+    /// Applies new parameters to a running sealer. The parameters that the
+    /// sealer does not have are ignored.
+    fn reconfigure(
+        &mut self,
+        _params: &SealerParams,
+    ) {
+    }
 }
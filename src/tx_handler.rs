@@ -1,24 +1,57 @@
+use crate::{tx_hash, Metrics};
 use tokio::sync::mpsc::UnboundedSender;
 
 #[derive(Clone)]
 /// Forwards received transactions to the batcher
 pub struct TxReceiveHandler<Tx> {
     tx_batcher: UnboundedSender<(Tx, usize)>,
+    metrics: Metrics,
 }
 
 impl<Tx> TxReceiveHandler<Tx> {
-    pub fn new(tx_batcher: UnboundedSender<(Tx, usize)>) -> Self {
-        Self { tx_batcher }
+    pub fn new(
+        tx_batcher: UnboundedSender<(Tx, usize)>,
+        metrics: Metrics,
+    ) -> Self {
+        Self {
+            tx_batcher,
+            metrics,
+        }
     }
 
-    /// Dispatch a received transaction to the batcher channel
+    /// Dispatch a transaction received from a client to the batcher channel
     pub fn dispatch(&self, msg: Tx)
+    where
+        Tx: serde::Serialize,
+    {
+        let _span = tracing::trace_span!("dispatch_tx", tx = %tx_hash(&msg)).entered();
+        self.metrics.latency.submitted(&msg);
+        if self.forward(msg) {
+            self.metrics.transactions_received.inc();
+        }
+    }
+
+    /// Feed a transaction of ours that was not committed back to the batcher
+    /// channel. It is not counted as received again.
+    pub fn reinject(&self, msg: Tx)
+    where
+        Tx: serde::Serialize,
+    {
+        let _span = tracing::trace_span!("reinject_tx", tx = %tx_hash(&msg)).entered();
+        self.forward(msg);
+    }
+
+    /// Sends a transaction to the batcher channel. Returns whether it was sent.
+    fn forward(&self, msg: Tx) -> bool
     where
         Tx: serde::Serialize,
     {
         let size = bincode::serialized_size(&msg).unwrap() as usize;
         if let Err(e) = self.tx_batcher.send((msg, size)) {
             log::error!("Tx Handler error: {}", e);
+            return false;
         }
+        tracing::trace!(size, "Sent transaction to the batcher");
+        true
     }
 }